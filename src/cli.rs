@@ -0,0 +1,146 @@
+use getopts::Options;
+
+pub struct Command {
+    pub name: &'static str,
+    pub arguments: &'static str,
+    pub description: &'static str,
+}
+
+// Invocations without command are handled like run, but accept the options of the commands
+// which were options before commands were introduced
+pub const LEGACY: &str = "legacy";
+
+pub const COMMANDS: [Command; 9] = [
+    Command {
+        name: "run",
+        arguments: "[/path/to/html]",
+        description: "Scan HTML data, store hashes of changed files and submit their URLs. This is the default command",
+    },
+    Command {
+        name: "scan",
+        arguments: "[/path/to/html]",
+        description: "Scan HTML data and store hashes of changed files without submitting their URLs, e.g. for the first run on an existing site",
+    },
+    Command {
+        name: "submit",
+        arguments: "",
+        description: "Submit URLs queued for submission by previous runs without scanning HTML data",
+    },
+    Command {
+        name: "url",
+        arguments: "<url> [<url> ...]",
+        description: "Submit URLs of the host of base_url right away, a single URL is submitted using the GET form of the IndexNow API",
+    },
+    Command {
+        name: "status",
+        arguments: "[/path/to/html]",
+        description: "Show state of the database and the number of pending changes",
+    },
+    Command {
+        name: "history",
+        arguments: "[<url>]",
+        description: "Show submission history of <url>, of URLs starting with <url> if it ends with * or of URLs below the path <url> on the host of base_url",
+    },
+    Command {
+        name: "db",
+        arguments: "export <file> | import <file> | vacuum | forget <file|url> [<file|url> ...] | migrate",
+        description: "Export the database to <file> or import it from <file> as JSON (- for standard input or output), compact the database, remove files or URLs from the database or migrate the database to the current schema version",
+    },
+    Command {
+        name: "key",
+        arguments: "generate [<directory>] | verify",
+        description: "Generate a new IndexNow key (and store the key file in <directory>) or verify that the key file is served at the key location",
+    },
+    Command {
+        name: "help",
+        arguments: "[<command>]",
+        description: "Show help text of <command>",
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// Options accepted by a command
+pub fn options(command: &str) -> Options {
+    let mut options = Options::new();
+
+    options.optflag("D", "debug", "Enable debug output");
+    options.optopt("c", "config", "Path to configuration file", "config_file");
+    options.optflag("h", "help", "Show help text");
+    options.optflag(
+        "q",
+        "quiet",
+        "Quiet operation, only warnings and errors are shown",
+    );
+    options.optopt("s", "site", "Only process site <name>", "name");
+    options.optflag("v", "version", "Show version information");
+
+    if [LEGACY, "run", "scan", "submit", "url", "db"].contains(&command) {
+        options.optflag(
+            "n",
+            "dry-run",
+            "Process data but show what would be done instead of doing it",
+        );
+    }
+    if [LEGACY, "run", "scan"].contains(&command) {
+        options.optflag(
+            "p",
+            "purge",
+            "Remove files no longer present in the filesystem (or the sitemap) from the database and submit their URLs as removed",
+        );
+        options.optflag(
+            "r",
+            "rehash",
+            "Calculate hashes of all files, even if size and modification time have not changed",
+        );
+    }
+    if [LEGACY, "run"].contains(&command) {
+        options.optopt(
+            "l",
+            "list",
+            "Only submit file names or URLs read from <file>, one per line. Use - to read from standard input",
+            "file",
+        );
+        options.optopt(
+            "R",
+            "range",
+            "Submit files changed in this revision range of the git repository, e.g. v1.0..main. Default is the range from the last submitted commit to HEAD",
+            "range",
+        );
+        options.optflag(
+            "u",
+            "update-hashes",
+            "Store hashes of the files read by --list in the database",
+        );
+        options.optflag(
+            "w",
+            "watch",
+            "Keep running and submit changed files as soon as they are written, moved or removed (Linux only)",
+        );
+    }
+    if [LEGACY, "status"].contains(&command) {
+        options.optflag("", "json", "Show status as JSON");
+    }
+    if [LEGACY, "history"].contains(&command) {
+        options.optopt(
+            "",
+            "since",
+            "Only show submissions since <time>, a RFC 3339 timestamp or a date (YYYY-MM-DD)",
+            "time",
+        );
+        options.optopt(
+            "",
+            "until",
+            "Only show submissions before <time>, a RFC 3339 timestamp or a date (YYYY-MM-DD)",
+            "time",
+        );
+    }
+    if command == LEGACY {
+        options.optflag("", "db-migrate", "Same as db migrate");
+        options.optflagopt("H", "history", "Same as history [<url>]", "url");
+    }
+
+    options
+}
@@ -0,0 +1,453 @@
+use crate::config;
+use crate::git;
+use crate::history;
+use crate::http;
+use crate::key;
+use crate::payload;
+use crate::scan;
+use crate::sitemap;
+use crate::sqlite3;
+#[cfg(target_os = "linux")]
+use crate::watch;
+
+use log::{debug, error, info};
+use simple_error::bail;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::process;
+
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    pub html_dir: Option<String>,
+    pub list: Option<String>,
+    pub range: Option<String>,
+    pub purge_old: bool,
+    pub dry_run: bool,
+    pub rehash: bool,
+    pub watch: bool,
+    pub update_hashes: bool,
+}
+
+/// Check command line options against the configuration of a site
+pub fn check_site_options(
+    config: &config::Configuration,
+    run_opts: &RunOptions,
+) -> Result<(), Box<dyn Error>> {
+    let name = &config.name;
+
+    // HTML data is not used if URLs are read from a sitemap or a git repository
+    let external_source =
+        config.sitemap.is_some() || config.git.is_some() || !config.roots.is_empty();
+    if external_source && run_opts.html_dir.is_some() {
+        bail!(
+            "Path to HTML data can't be used if roots, a sitemap or a git repository are configured (site {})",
+            name
+        );
+    }
+    if !external_source && run_opts.html_dir.is_none() && run_opts.list.is_none() {
+        bail!("Missing path to HTML data is missing (site {})", name);
+    }
+    if run_opts.watch && (config.sitemap.is_some() || config.git.is_some()) {
+        bail!("Watch mode can't be used if a sitemap or a git repository is configured");
+    }
+    if run_opts.watch && run_opts.list.is_some() {
+        bail!("Watch mode can't be used together with a list of files");
+    }
+    if run_opts.update_hashes && run_opts.list.is_none() {
+        bail!("Hashes can only be updated for a list of files");
+    }
+    if run_opts.range.is_some() && config.git.is_none() {
+        bail!("Revision range can only be used if a git repository is configured");
+    }
+    Ok(())
+}
+
+/// Directories of HTML data of a site and the URLs they are mapped to
+pub fn site_roots(
+    config: &config::Configuration,
+    html_dir: &Option<String>,
+) -> Vec<config::HtmlRoot> {
+    match (&config.git, html_dir) {
+        (Some(git_cfg), _) => vec![config::HtmlRoot::new(
+            &git::html_dir(git_cfg),
+            &config.base_url,
+        )],
+        (None, Some(dir)) => vec![config::HtmlRoot::new(dir, &config.base_url)],
+        (None, None) => config.roots.clone(),
+    }
+}
+
+fn open_database(config: &config::Configuration) -> Result<rusqlite::Connection, Box<dyn Error>> {
+    debug!("Parsed configuration: {:?}", config);
+    debug!("Opening database connection to {}", config.database);
+    match sqlite3::open(&config.database) {
+        Ok(v) => Ok(v),
+        Err(e) => bail!("Can't open databse file {}: {}", config.database, e),
+    }
+}
+
+/// Build list of changes for a site and submit them
+pub fn run_site(
+    config: &config::Configuration,
+    run_opts: &RunOptions,
+) -> Result<(), Box<dyn Error>> {
+    let dry_run = run_opts.dry_run;
+    let roots = site_roots(config, &run_opts.html_dir);
+    let mut db_handle = open_database(config)?;
+
+    if let Some(list_file) = &run_opts.list {
+        let entries = match read_list(list_file) {
+            Ok(v) => v,
+            Err(e) => bail!("Can't read list of files from {}: {}", list_file, e),
+        };
+        return payload::submit_list(
+            config,
+            &mut db_handle,
+            &roots,
+            entries,
+            run_opts.update_hashes,
+            dry_run,
+        );
+    }
+
+    if let Some(git_cfg) = &config.git {
+        let extlist = match &config.file_extensions {
+            Some(v) => v,
+            None => bail!("List of file extensions is empty"),
+        };
+        let changes =
+            match git::build_update_list(&db_handle, git_cfg, extlist, run_opts.range.as_deref()) {
+                Ok(v) => v,
+                Err(e) => bail!(
+                    "Unable to build list of changes from git repository {}: {}",
+                    git_cfg.repository,
+                    e
+                ),
+            };
+        let indexnow =
+            payload::build_path_pending_list(config, &roots[0], changes.changed, changes.deleted);
+        payload::submit_pending(config, &mut db_handle, &roots, indexnow, dry_run)?;
+
+        // Commit is stored after successful submission, unless an older revision range was
+        // submitted
+        if dry_run {
+            return Ok(());
+        }
+        if !git::is_newer_than_state(&db_handle, git_cfg, &changes.commit)? {
+            info!(
+                "Keeping last submitted commit, {} is not newer",
+                changes.commit
+            );
+            return Ok(());
+        }
+        debug!("Storing {} as last submitted commit", changes.commit);
+        if let Err(e) = sqlite3::state_set(&db_handle, &git::state_key(git_cfg), &changes.commit) {
+            bail!("Unable to update database: {}", e);
+        }
+        return Ok(());
+    }
+
+    if let Some(sitemap_cfg) = &config.sitemap {
+        let changes = match sitemap::build_update_list(
+            &mut db_handle,
+            config,
+            sitemap_cfg,
+            run_opts.purge_old,
+        ) {
+            Ok(v) => v,
+            Err(e) => bail!(
+                "Unable to read URLs from sitemap {}: {}",
+                sitemap_cfg.location,
+                e
+            ),
+        };
+        // Even without changes, URLs from the queue of pending submissions must be processed
+        if changes.is_empty() {
+            info!("List of updated files is empty");
+        }
+        let indexnow =
+            payload::pending_from_changes(config, &mut db_handle, None, changes, dry_run)?;
+        return payload::submit_pending(config, &mut db_handle, &roots, indexnow, dry_run);
+    }
+
+    // Watch mode scans all files too before waiting for changes
+    if run_opts.watch {
+        run_watch(
+            config,
+            &mut db_handle,
+            &roots,
+            run_opts.purge_old,
+            run_opts.rehash,
+            dry_run,
+        );
+    }
+    payload::submit_roots(
+        config,
+        &mut db_handle,
+        &roots,
+        None,
+        run_opts.purge_old,
+        run_opts.rehash,
+        dry_run,
+    )
+}
+
+/// Build list of changes for a site and store them in the database without submitting them
+pub fn scan_site(
+    config: &config::Configuration,
+    run_opts: &RunOptions,
+) -> Result<(), Box<dyn Error>> {
+    let dry_run = run_opts.dry_run;
+    let roots = site_roots(config, &run_opts.html_dir);
+    let mut db_handle = open_database(config)?;
+
+    if let Some(git_cfg) = &config.git {
+        let extlist = match &config.file_extensions {
+            Some(v) => v,
+            None => bail!("List of file extensions is empty"),
+        };
+        let changes = match git::build_update_list(&db_handle, git_cfg, extlist, None) {
+            Ok(v) => v,
+            Err(e) => bail!(
+                "Unable to build list of changes from git repository {}: {}",
+                git_cfg.repository,
+                e
+            ),
+        };
+        if dry_run {
+            info!("Would store {} as last submitted commit", changes.commit);
+            return Ok(());
+        }
+        info!("Storing {} as last submitted commit", changes.commit);
+        if let Err(e) = sqlite3::state_set(&db_handle, &git::state_key(git_cfg), &changes.commit) {
+            bail!("Unable to update database: {}", e);
+        }
+        return Ok(());
+    }
+
+    let mut lists = Vec::<scan::UpdateList>::new();
+    if let Some(sitemap_cfg) = &config.sitemap {
+        match sitemap::build_update_list(&mut db_handle, config, sitemap_cfg, run_opts.purge_old) {
+            Ok(v) => lists.push(v),
+            Err(e) => bail!(
+                "Unable to read URLs from sitemap {}: {}",
+                sitemap_cfg.location,
+                e
+            ),
+        };
+    } else {
+        let extlist = match &config.file_extensions {
+            Some(v) => v,
+            None => bail!("List of file extensions is empty"),
+        };
+        for root in &roots {
+            match scan::build_update_list(
+                &root.path,
+                &mut db_handle,
+                config,
+                extlist,
+                run_opts.purge_old,
+                run_opts.rehash,
+                None,
+            ) {
+                Ok(v) => lists.push(v),
+                Err(e) => bail!("Unable to build file list for {}: {}", root.path, e),
+            };
+        }
+    }
+
+    let mut upd = Vec::<scan::Filehash>::new();
+    let mut del = Vec::<String>::new();
+    for mut list in lists {
+        upd.append(&mut list.changed);
+        upd.append(&mut list.refreshed);
+        del.append(&mut list.deleted);
+    }
+    if dry_run {
+        info!(
+            "Would store hashes of {} files and remove {} files from the database",
+            upd.len(),
+            del.len()
+        );
+        return Ok(());
+    }
+    if let Err(e) = sqlite3::db_update(&mut db_handle, &upd, &del) {
+        bail!("Unable to update database: {}", e);
+    }
+    info!(
+        "Stored hashes of {} files and removed {} files from the database",
+        upd.len(),
+        del.len()
+    );
+    Ok(())
+}
+
+/// Submit URLs queued by previous runs
+pub fn submit_site(config: &config::Configuration, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    let roots = site_roots(config, &None);
+    let mut db_handle = open_database(config)?;
+    payload::submit_pending(config, &mut db_handle, &roots, Vec::new(), dry_run)
+}
+
+/// Submit URLs given on the command line
+pub fn submit_urls(
+    config: &config::Configuration,
+    urls: &[String],
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut db_handle = open_database(config)?;
+    payload::submit_urls(config, &mut db_handle, urls, dry_run)
+}
+
+pub fn show_history(
+    config: &config::Configuration,
+    filter: Option<&str>,
+    since: Option<&str>,
+    until: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let db_handle = open_database(config)?;
+    history::show(config, &db_handle, filter, since, until)
+}
+
+/// Write content of the database as JSON to a file or, if file is -, to stdout
+pub fn db_export(config: &config::Configuration, file: &str) -> Result<(), Box<dyn Error>> {
+    let db_handle = sqlite3::open_existing(&config.database)?;
+    let data = serde_json::to_string_pretty(&sqlite3::export(&db_handle)?)?;
+    if file == "-" {
+        println!("{}", data);
+    } else {
+        info!("Exporting database {} to {}", config.database, file);
+        fs::write(file, data)?;
+    }
+    Ok(())
+}
+
+/// Replace content of the database by data read from a file or, if file is -, from stdin
+pub fn db_import(
+    config: &config::Configuration,
+    file: &str,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let raw = if file == "-" {
+        let mut buffer = String::new();
+        io::stdin().read_to_string(&mut buffer)?;
+        buffer
+    } else {
+        fs::read_to_string(file)?
+    };
+    let data: serde_json::Value = serde_json::from_str(&raw)?;
+
+    let mut db_handle = open_database(config)?;
+    let count = sqlite3::import(&mut db_handle, &data, dry_run)?;
+    if dry_run {
+        info!(
+            "Would import {} rows from {} into database {}",
+            count, file, config.database
+        );
+    } else {
+        info!(
+            "Imported {} rows from {} into database {}",
+            count, file, config.database
+        );
+    }
+    Ok(())
+}
+
+pub fn db_vacuum(config: &config::Configuration) -> Result<(), Box<dyn Error>> {
+    let db_handle = sqlite3::open_existing(&config.database)?;
+    let before = fs::metadata(&config.database)?.len();
+    sqlite3::vacuum(&db_handle)?;
+    info!(
+        "Compacted database {} from {} to {} bytes",
+        config.database,
+        before,
+        fs::metadata(&config.database)?.len()
+    );
+    Ok(())
+}
+
+/// Remove files and URLs from the database, so they will be submitted again by the next run.
+/// Relative file names are relative to the directories of the HTML data.
+pub fn db_forget(
+    config: &config::Configuration,
+    entries: &[String],
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let roots = site_roots(config, &None);
+    let mut expanded = Vec::<String>::new();
+    for entry in entries {
+        if !http::is_url(entry) && !entry.starts_with('/') {
+            for root in &roots {
+                expanded.push(format!("{}{}", root.path, entry.trim_start_matches("./")));
+            }
+        }
+        expanded.push(entry.clone());
+    }
+
+    let mut db_handle = sqlite3::open_existing(&config.database)?;
+    let (files, queued) = sqlite3::forget(&mut db_handle, &expanded, dry_run)?;
+    if dry_run {
+        info!(
+            "Would remove {} files and {} queued URLs from database {}",
+            files, queued, config.database
+        );
+    } else {
+        info!(
+            "Removed {} files and {} queued URLs from database {}",
+            files, queued, config.database
+        );
+    }
+    Ok(())
+}
+
+pub fn key_verify(config: &config::Configuration) -> Result<(), Box<dyn Error>> {
+    key::verify(config)
+}
+
+#[cfg(target_os = "linux")]
+fn run_watch(
+    config: &config::Configuration,
+    db_handle: &mut rusqlite::Connection,
+    roots: &[config::HtmlRoot],
+    purge: bool,
+    rehash: bool,
+    dry_run: bool,
+) {
+    if let Err(e) = watch::watch(config, db_handle, roots, purge, rehash, dry_run) {
+        error!("Watching HTML data failed: {}", e);
+    }
+    process::exit(1);
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run_watch(
+    _config: &config::Configuration,
+    _db_handle: &mut rusqlite::Connection,
+    _roots: &[config::HtmlRoot],
+    _purge: bool,
+    _rehash: bool,
+    _dry_run: bool,
+) {
+    error!("Watch mode is only supported on Linux");
+    process::exit(1);
+}
+
+/// Read file names or URLs, one per line. Empty lines and comments are ignored.
+fn read_list(f: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let raw = if f == "-" {
+        let mut buffer = String::new();
+        io::stdin().read_to_string(&mut buffer)?;
+        buffer
+    } else {
+        fs::read_to_string(f)?
+    };
+
+    Ok(raw
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.to_string())
+        .collect())
+}
@@ -1,5 +1,9 @@
+use crate::constants;
+use crate::html;
+
 use regex::Regex;
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use simple_error::bail;
 use std::error::Error;
 use std::fs;
@@ -7,22 +11,329 @@ use url::Url;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Configuration {
+    #[serde(default)]
+    pub name: String,
     pub database: String,
     pub file_extensions: Option<Vec<String>>,
     pub exclude: Option<Vec<String>>,
     pub base_url: String,
-    pub submit: String,
+    pub submit: StringOrList,
     pub key: String,
     pub key_location: Option<String>,
+    #[serde(default)]
+    pub retry: RetryConfiguration,
+    #[serde(default)]
+    pub verify_deleted: bool,
+    #[serde(default)]
+    pub check_inode: bool,
+    pub hash_threads: Option<usize>,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    pub normalise: Option<Normalisation>,
+    #[serde(default)]
+    pub html: HtmlConfiguration,
+    pub robots_txt: Option<RobotsConfiguration>,
+    pub sitemap: Option<SitemapConfiguration>,
+    pub git: Option<GitConfiguration>,
+    #[serde(default)]
+    pub roots: Vec<HtmlRoot>,
+    #[serde(default)]
+    pub watch: WatchConfiguration,
+    #[serde(default)]
+    pub history: HistoryConfiguration,
+    #[serde(default)]
+    pub url_mapping: UrlMapping,
+    pub rewrite: Option<Vec<RewriteRule>>,
     #[serde(skip)]
     pub host: String,
     #[serde(skip)]
     pub exclude_list: Vec<Regex>,
+    #[serde(skip)]
+    pub submit_list: Vec<String>,
+    #[serde(skip)]
+    pub rewrite_list: Vec<(Regex, String)>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RewriteRule {
+    pub pattern: String,
+    pub replacement: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum StringOrList {
+    Single(String),
+    List(Vec<String>),
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Blake3,
+    Sha256,
+    #[default]
+    Sha512,
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Xxh3 => "xxh3",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "blake3" => Some(HashAlgorithm::Blake3),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "sha512" => Some(HashAlgorithm::Sha512),
+            "xxh3" => Some(HashAlgorithm::Xxh3),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Normalisation {
+    #[serde(default = "default_html_extensions")]
+    pub extensions: Vec<String>,
+    #[serde(default)]
+    pub strip: Vec<String>,
+    #[serde(default)]
+    pub ignore_generator: bool,
+    #[serde(default)]
+    pub elements: Vec<String>,
+    #[serde(skip)]
+    pub strip_list: Vec<regex::bytes::Regex>,
+    #[serde(skip)]
+    pub element_list: Vec<regex::bytes::Regex>,
+    #[serde(skip)]
+    pub fingerprint: String,
+}
+
+fn default_html_extensions() -> Vec<String> {
+    vec!["html".to_string(), "htm".to_string()]
 }
 
-pub fn parse_config_file(f: &str) -> Result<Configuration, Box<dyn Error>> {
+#[derive(Clone, Debug, Deserialize)]
+pub struct RobotsConfiguration {
+    pub path: Option<String>,
+    #[serde(default = "default_robots_user_agent")]
+    pub user_agent: String,
+}
+
+fn default_robots_user_agent() -> String {
+    "*".to_string()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct HtmlRoot {
+    pub path: String,
+    pub url_prefix: Option<String>,
+    #[serde(skip)]
+    pub base_url: String,
+}
+
+impl HtmlRoot {
+    pub fn new(path: &str, base_url: &str) -> Self {
+        let mut root = HtmlRoot {
+            path: path.to_string(),
+            url_prefix: None,
+            base_url: base_url.to_string(),
+        };
+        while root.path.ends_with('/') {
+            root.path.pop();
+        }
+        root.path.push('/');
+        root
+    }
+}
+
+/// Root containing the file
+pub fn root_for_file<'a>(roots: &'a [HtmlRoot], file: &str) -> Option<&'a HtmlRoot> {
+    roots
+        .iter()
+        .filter(|r| file.starts_with(&r.path))
+        .max_by_key(|r| r.path.len())
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GitConfiguration {
+    pub repository: String,
+    #[serde(default)]
+    pub path: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SitemapConfiguration {
+    pub location: String,
+    #[serde(default)]
+    pub change_detection: ChangeDetection,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeDetection {
+    #[default]
+    Lastmod,
+    Hash,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct HtmlConfiguration {
+    pub extensions: Vec<String>,
+    pub noindex: bool,
+    pub canonical: bool,
+    #[serde(skip)]
+    pub regex: Option<html::HtmlRegex>,
+}
+
+impl Default for HtmlConfiguration {
+    fn default() -> Self {
+        HtmlConfiguration {
+            extensions: default_html_extensions(),
+            noindex: true,
+            canonical: true,
+            regex: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct UrlMapping {
+    pub directory_index: Vec<String>,
+    pub strip_extensions: Vec<String>,
+    pub trailing_slash: TrailingSlash,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    #[default]
+    Keep,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RetryConfiguration {
+    pub max_retries: u32,
+    pub initial_delay: u64,
+    pub max_delay: u64,
+    pub max_total: u64,
+}
+
+impl Default for RetryConfiguration {
+    fn default() -> Self {
+        RetryConfiguration {
+            max_retries: constants::DEFAULT_MAX_RETRIES,
+            initial_delay: constants::DEFAULT_RETRY_INITIAL_DELAY,
+            max_delay: constants::DEFAULT_RETRY_MAX_DELAY,
+            max_total: constants::DEFAULT_RETRY_MAX_TOTAL,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WatchConfiguration {
+    pub debounce: u64,
+    pub max_delay: u64,
+}
+
+impl Default for WatchConfiguration {
+    fn default() -> Self {
+        WatchConfiguration {
+            debounce: constants::DEFAULT_WATCH_DEBOUNCE,
+            max_delay: constants::DEFAULT_WATCH_MAX_DELAY,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfiguration {
+    pub retention: u64,
+}
+
+impl Default for HistoryConfiguration {
+    fn default() -> Self {
+        HistoryConfiguration {
+            retention: constants::DEFAULT_HISTORY_RETENTION,
+        }
+    }
+}
+
+/// Parse configuration file, returns the configuration of every site. Settings of a site in the
+/// list of sites replace the settings at the top level.
+pub fn parse_config_file(f: &str) -> Result<Vec<Configuration>, Box<dyn Error>> {
     let raw = fs::read_to_string(f)?;
-    let mut result: Configuration = match serde_yaml::from_str(raw.as_str()) {
+    let mut top: serde_yaml::Value = serde_yaml::from_str(raw.as_str())?;
+    let sites = match top.as_mapping_mut() {
+        Some(v) => v.remove(&serde_yaml::Value::from("sites")),
+        None => bail!("Configuration is not a mapping of settings"),
+    };
+
+    let mut result = Vec::<Configuration>::new();
+    match sites {
+        None => result.push(parse_site(top)?),
+        Some(serde_yaml::Value::Sequence(list)) => {
+            if list.is_empty() {
+                bail!("List of sites is empty");
+            }
+            for (i, site) in list.into_iter().enumerate() {
+                let site_map = match site {
+                    serde_yaml::Value::Mapping(v) => v,
+                    _ => bail!("Site #{} is not a mapping of settings", i + 1),
+                };
+                let label = match site_map.get(&serde_yaml::Value::from("name")) {
+                    Some(serde_yaml::Value::String(v)) => v.clone(),
+                    _ => format!("#{}", i + 1),
+                };
+
+                let mut merged = top.clone();
+                if let Some(m) = merged.as_mapping_mut() {
+                    for (k, v) in site_map {
+                        m.insert(k, v);
+                    }
+                }
+                match parse_site(merged) {
+                    Ok(v) => result.push(v),
+                    Err(e) => bail!("Site {}: {}", label, e),
+                };
+            }
+        }
+        Some(_) => bail!("sites is not a list"),
+    };
+
+    for (i, site) in result.iter().enumerate() {
+        for other in &result[i + 1..] {
+            if site.name == other.name {
+                bail!("Site name {} is used more than once", site.name);
+            }
+            if site.database == other.database {
+                bail!(
+                    "Sites {} and {} use the same database {}",
+                    site.name,
+                    other.name,
+                    site.database
+                );
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn parse_site(value: serde_yaml::Value) -> Result<Configuration, Box<dyn Error>> {
+    let mut result: Configuration = match serde_yaml::from_value(value) {
         Ok(v) => v,
         Err(e) => return Err(Box::new(e)),
     };
@@ -39,6 +350,70 @@ pub fn parse_config_file(f: &str) -> Result<Configuration, Box<dyn Error>> {
             bail!("Can't extract hostname from base_url {}", result.base_url);
         }
     };
+    if result.name.is_empty() {
+        result.name = result.host.clone();
+    }
+    result.submit_list = match &result.submit {
+        StringOrList::Single(v) => vec![v.clone()],
+        StringOrList::List(v) => v.clone(),
+    };
+    if result.submit_list.is_empty() {
+        bail!("List of IndexNow endpoints to submit to is empty");
+    }
+    for endpoint in &result.submit_list {
+        if let Err(e) = Url::parse(endpoint) {
+            bail!("Can't parse IndexNow endpoint {} as URL: {}", endpoint, e);
+        }
+    }
+
+    if result.retry.initial_delay > result.retry.max_delay {
+        bail!(
+            "Initial retry delay of {} seconds is larger than maximal retry delay of {} seconds",
+            result.retry.initial_delay,
+            result.retry.max_delay
+        );
+    }
+
+    if result.watch.debounce > result.watch.max_delay {
+        bail!(
+            "Debounce time of {} seconds is larger than maximal delay of {} seconds for watch mode",
+            result.watch.debounce,
+            result.watch.max_delay
+        );
+    }
+
+    if result.sitemap.is_some() && result.git.is_some() {
+        bail!("Only one of sitemap or git can be used as source of changes");
+    }
+    if !result.roots.is_empty() && (result.sitemap.is_some() || result.git.is_some()) {
+        bail!("roots can't be used together with sitemap or git");
+    }
+
+    // URL prefix is relative to base_url, but can be an absolute path or URL too
+    for root in result.roots.iter_mut() {
+        let base_url = match &root.url_prefix {
+            Some(prefix) => {
+                let mut joined = parsed.join(prefix)?.to_string();
+                if !joined.ends_with('/') {
+                    joined.push('/');
+                }
+                joined
+            }
+            None => result.base_url.clone(),
+        };
+        let url_prefix = root.url_prefix.take();
+        *root = HtmlRoot::new(&root.path, &base_url);
+        root.url_prefix = url_prefix;
+    }
+    // Files of nested roots would be scanned twice
+    for root in &result.roots {
+        for other in &result.roots {
+            if !std::ptr::eq(root, other) && other.path.starts_with(&root.path) {
+                bail!("Root {} is located below root {}", other.path, root.path);
+            }
+        }
+    }
+
     if let Some(excl) = &result.exclude {
         for rxp in excl {
             let re = match Regex::new(rxp) {
@@ -50,5 +425,67 @@ pub fn parse_config_file(f: &str) -> Result<Configuration, Box<dyn Error>> {
             result.exclude_list.push(re);
         }
     }
+
+    if let Some(norm) = result.normalise.as_mut() {
+        parse_normalisation(norm)?;
+    }
+
+    if result.html.noindex || result.html.canonical {
+        result.html.regex = Some(html::build_regex()?);
+    }
+
+    if let Some(rules) = &result.rewrite {
+        for rule in rules {
+            let re = match Regex::new(&rule.pattern) {
+                Ok(v) => v,
+                Err(e) => {
+                    bail!(
+                        "Can't parse {} of rewrite rule as regular expression: {}",
+                        rule.pattern,
+                        e
+                    );
+                }
+            };
+            result.rewrite_list.push((re, rule.replacement.clone()));
+        }
+    }
     Ok(result)
 }
+
+fn parse_normalisation(norm: &mut Normalisation) -> Result<(), Box<dyn Error>> {
+    if norm.ignore_generator {
+        norm.strip_list.push(regex::bytes::Regex::new(
+            constants::HTML_META_GENERATOR_REGEX,
+        )?);
+    }
+
+    for rxp in &norm.strip {
+        let re = match regex::bytes::Regex::new(rxp) {
+            Ok(v) => v,
+            Err(e) => {
+                bail!("Can't parse {} as regular expression: {}", rxp, e);
+            }
+        };
+        norm.strip_list.push(re);
+    }
+
+    for element in &norm.elements {
+        if element.is_empty() || !element.chars().all(|c| c.is_ascii_alphanumeric()) {
+            bail!("{} is not a valid name of a HTML element", element);
+        }
+        let rxp = format!(
+            r"(?is)<{}(?:\s[^>]*)?>(.*)</{}\s*>",
+            regex::escape(element),
+            regex::escape(element)
+        );
+        norm.element_list.push(regex::bytes::Regex::new(&rxp)?);
+    }
+
+    // Stored with every hash to detect changed normalisation settings
+    let settings = format!(
+        "{:?}|{:?}|{}|{:?}",
+        norm.extensions, norm.strip, norm.ignore_generator, norm.elements
+    );
+    norm.fingerprint = hex::encode(&Sha256::digest(settings.as_bytes())[..8]);
+    Ok(())
+}
@@ -1,13 +1,101 @@
 pub const NAME: &str = "indexnow-updater";
 pub const VERSION: &str = "1.0.1";
 pub const REPO: &str = "https://git.ypbind.de/cgit/indexnow-updater";
-pub const SQLITE3_SCHEMA: &str = "CREATE TABLE \"files\" (
+pub const SQLITE3_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS \"files\" (
     filename TEXT PRIMARY KEY,
-    sha512 VARCHAR(128)
+    hash VARCHAR(128),
+    algorithm TEXT,
+    normalisation TEXT,
+    size INTEGER,
+    mtime INTEGER,
+    inode INTEGER
 );";
+// Columns added to the tables before schema versions were introduced
+pub const SQLITE3_FILES_COLUMNS: [(&str, &str); 5] = [
+    ("algorithm", "TEXT"),
+    ("normalisation", "TEXT"),
+    ("size", "INTEGER"),
+    ("mtime", "INTEGER"),
+    ("inode", "INTEGER"),
+];
+pub const SQLITE3_QUEUE_COLUMNS: [(&str, &str); 2] =
+    [("algorithm", "TEXT"), ("normalisation", "TEXT")];
+// Columns renamed before schema versions were introduced (old name, new name)
+pub const SQLITE3_RENAMED_COLUMNS: [(&str, &str); 1] = [("sha512", "hash")];
+pub const SQLITE3_QUEUE_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS \"queue\" (
+    url TEXT NOT NULL,
+    endpoint TEXT NOT NULL,
+    filename TEXT,
+    hash VARCHAR(128),
+    algorithm TEXT,
+    normalisation TEXT,
+    deleted INTEGER NOT NULL DEFAULT 0,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (url, endpoint)
+);";
+pub const SQLITE3_STATE_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS \"state\" (
+    key TEXT PRIMARY KEY,
+    value TEXT
+);";
+pub const SQLITE3_HISTORY_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS \"history\" (
+    batch TEXT NOT NULL,
+    endpoint TEXT NOT NULL,
+    url TEXT NOT NULL,
+    deleted INTEGER NOT NULL DEFAULT 0,
+    timestamp INTEGER NOT NULL,
+    status INTEGER,
+    response TEXT,
+    error TEXT
+);
+CREATE INDEX IF NOT EXISTS \"history_url\" ON \"history\" (url);
+CREATE INDEX IF NOT EXISTS \"history_timestamp\" ON \"history\" (timestamp);";
+// Tables of the export of the database
+pub const SQLITE3_EXPORT_TABLES: [&str; 4] = ["files", "queue", "state", "history"];
+pub const HTML_META_GENERATOR_REGEX: &str =
+    r#"(?is)<meta\s[^>]*name\s*=\s*["']?generator["']?[^>]*>"#;
+pub const HTML_HEAD_END_REGEX: &str = r"(?i)</head";
+pub const HTML_META_LINK_TAG_REGEX: &str = r"(?is)<(meta|link)(\s[^>]*)>";
+pub const HTML_ATTRIBUTE_REGEX: &str =
+    r#"(?s)([a-zA-Z_:][-a-zA-Z0-9_:.]*)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+))"#;
+pub const SITEMAP_ENTRY_REGEX: &str = r"(?is)<(url|sitemap)(?:\s[^>]*)?>(.*?)</(?:url|sitemap)\s*>";
+pub const SITEMAP_LOC_REGEX: &str = r"(?is)<loc(?:\s[^>]*)?>(.*?)</loc\s*>";
+pub const SITEMAP_LASTMOD_REGEX: &str = r"(?is)<lastmod(?:\s[^>]*)?>(.*?)</lastmod\s*>";
+// Sitemap indexes must not be nested, but some sites do it anyway
+pub const SITEMAP_MAX_DEPTH: usize = 3;
+// Stored as normalisation of URLs detected by <lastmod> instead of the hash of the page
+pub const SITEMAP_LASTMOD_MARKER: &str = "lastmod";
 pub const BATCH_SIZE: usize = 9000;
 pub const DEFAULT_TIMEOUT: u64 = 300;
+pub const DEFAULT_MAX_RETRIES: u32 = 5;
+pub const DEFAULT_RETRY_INITIAL_DELAY: u64 = 5;
+pub const DEFAULT_RETRY_MAX_DELAY: u64 = 300;
+pub const DEFAULT_RETRY_MAX_TOTAL: u64 = 900;
+pub const DEFAULT_WATCH_DEBOUNCE: u64 = 5;
+pub const DEFAULT_WATCH_MAX_DELAY: u64 = 300;
+// Size of the buffer for inotify events, must hold at least one event with the maximal file name length
+pub const WATCH_EVENT_BUFFER_SIZE: usize = 65536;
+// Delay in seconds before a failed submission is retried, doubled on every failed attempt
+pub const QUEUE_RETRY_DELAY: i64 = 900;
+pub const QUEUE_MAX_RETRY_DELAY: i64 = 86400;
+// Number of characters of the response body stored in the submission history
+pub const REPLY_BODY_EXCERPT: usize = 512;
+// Keys can contain 8 to 128 characters of a-z, A-Z, 0-9 and -
+pub const KEY_MIN_LENGTH: usize = 8;
+pub const KEY_MAX_LENGTH: usize = 128;
+pub const KEY_LENGTH: usize = 32;
+pub const KEY_CHARACTERS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+// Days to keep the submission history
+pub const DEFAULT_HISTORY_RETENTION: u64 = 365;
 
 pub fn generate_user_agent() -> String {
     format!("{}/{} ({})", NAME, VERSION, REPO)
 }
+
+pub fn default_hash_threads() -> usize {
+    match std::thread::available_parallelism() {
+        Ok(v) => v.get(),
+        Err(_) => 1,
+    }
+}
@@ -0,0 +1,226 @@
+use crate::config;
+use crate::scan;
+use crate::sqlite3;
+
+use log::{debug, info, warn};
+use simple_error::bail;
+use std::error::Error;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Debug, Default)]
+pub struct GitChanges {
+    // Commit at the end of the revision range
+    pub commit: String,
+    pub changed: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+/// Directory of the HTML data in the work tree of the repository
+pub fn html_dir(git_cfg: &config::GitConfiguration) -> String {
+    let mut dir = git_cfg.repository.trim_end_matches('/').to_string();
+    dir.push('/');
+    let path = git_cfg.path.trim_matches('/');
+    if !path.is_empty() {
+        dir.push_str(path);
+        dir.push('/');
+    }
+    dir
+}
+
+/// Key of the last submitted commit in the state table of the database
+pub fn state_key(git_cfg: &config::GitConfiguration) -> String {
+    format!("git_commit:{}", git_cfg.repository.trim_end_matches('/'))
+}
+
+/// Build list of added, modified and deleted files from the diff of a revision range. Without
+/// a range, the diff between the last submitted commit and HEAD is used.
+pub fn build_update_list(
+    db: &rusqlite::Connection,
+    git_cfg: &config::GitConfiguration,
+    extlist: &[String],
+    range: Option<&str>,
+) -> Result<GitChanges, Box<dyn Error>> {
+    let repo = git_cfg.repository.trim_end_matches('/');
+
+    let (from, to) = match range {
+        Some(r) if r.contains("...") => bail!(
+            "Symmetric difference {} is not supported, use a revision range <from>..<to>",
+            r
+        ),
+        Some(r) => match r.split_once("..") {
+            Some((f, t)) => (
+                Some(f.to_string()),
+                if t.is_empty() { "HEAD" } else { t }.to_string(),
+            ),
+            None => (Some(r.to_string()), "HEAD".to_string()),
+        },
+        None => (
+            sqlite3::state_get(db, &state_key(git_cfg))?,
+            "HEAD".to_string(),
+        ),
+    };
+
+    let commit = rev_parse(repo, &to)?;
+    let from = match from {
+        Some(v) => rev_parse(repo, &v)?,
+        None => {
+            info!(
+                "No submitted commit of {} found in database, using all files of {}",
+                repo, to
+            );
+            empty_tree(repo)?
+        }
+    };
+    if from == commit {
+        info!("No new commits in {} since {}", repo, commit);
+        return Ok(GitChanges {
+            commit,
+            ..Default::default()
+        });
+    }
+    debug!(
+        "Building list of changes in {} from {} to {}",
+        repo, from, commit
+    );
+
+    let path = git_cfg.path.trim_matches('/');
+    let pathspec = if path.is_empty() { "." } else { path };
+
+    // Renames are reported as deletion and addition, because the old URL is gone
+    let output = git(
+        repo,
+        &[
+            "diff",
+            "--name-status",
+            "--no-renames",
+            "-z",
+            &from,
+            &commit,
+            "--",
+            pathspec,
+        ],
+    )?;
+
+    let mut result = GitChanges {
+        commit,
+        ..Default::default()
+    };
+    let mut fields = output.split('\0').filter(|f| !f.is_empty());
+    while let (Some(status), Some(file)) = (fields.next(), fields.next()) {
+        if !scan::match_extension_list(Path::new(file), extlist) {
+            debug!(
+                "Skipping {} because extension does not match the extension list {:?}",
+                file, extlist
+            );
+            continue;
+        }
+
+        let fname = format!("{}/{}", repo, file);
+        match status.chars().next() {
+            Some('A') | Some('M') | Some('T') => result.changed.push(fname),
+            Some('D') => result.deleted.push(fname),
+            _ => warn!(
+                "Unknown status {} of {} in git diff - skipping",
+                status, file
+            ),
+        };
+    }
+
+    info!(
+        "Pending changes: {} added or modified files, {} deleted files",
+        result.changed.len(),
+        result.deleted.len()
+    );
+    Ok(result)
+}
+
+/// Check if commit is a descendant of the last submitted commit, so storing it will not move
+/// the last submitted commit back, e.g. after submitting an older revision range
+pub fn is_newer_than_state(
+    db: &rusqlite::Connection,
+    git_cfg: &config::GitConfiguration,
+    commit: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let repo = git_cfg.repository.trim_end_matches('/');
+    let stored = match sqlite3::state_get(db, &state_key(git_cfg))? {
+        Some(v) => v,
+        None => return Ok(true),
+    };
+    // A stored commit which no longer exists, e.g. after rewriting the history, is replaced
+    if rev_parse(repo, &stored).is_err() {
+        return Ok(true);
+    }
+
+    debug!(
+        "Running git -C {} merge-base --is-ancestor {} {}",
+        repo, stored, commit
+    );
+    let status = match Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["merge-base", "--is-ancestor", &stored, commit])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+    {
+        Ok(v) => v,
+        Err(e) => bail!("can't run git: {}", e),
+    };
+    match status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => bail!(
+            "git merge-base --is-ancestor {} {} failed in {}",
+            stored,
+            commit,
+            repo
+        ),
+    }
+}
+
+fn rev_parse(repo: &str, rev: &str) -> Result<String, Box<dyn Error>> {
+    match git(
+        repo,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{}^{{commit}}", rev),
+        ],
+    ) {
+        Ok(v) => Ok(v.trim().to_string()),
+        Err(_) => bail!("{} is not a valid commit in {}", rev, repo),
+    }
+}
+
+// The object ID of the empty tree depends on the hash algorithm of the repository
+fn empty_tree(repo: &str) -> Result<String, Box<dyn Error>> {
+    let tree = git(repo, &["hash-object", "-t", "tree", "--stdin"])?;
+    Ok(tree.trim().to_string())
+}
+
+fn git(repo: &str, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    debug!("Running git -C {} {}", repo, args.join(" "));
+    let output = match Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(v) => v,
+        Err(e) => bail!("can't run git: {}", e),
+    };
+
+    if !output.status.success() {
+        bail!(
+            "git {} failed in {}: {}",
+            args.join(" "),
+            repo,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
@@ -0,0 +1,125 @@
+use crate::config::{HashAlgorithm, Normalisation};
+
+use sha2::{Digest, Sha256, Sha512};
+use std::fs;
+use std::io;
+use std::io::Read;
+
+const READ_BUFFER_SIZE: usize = 65536;
+
+enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl Hasher {
+    fn new(algorithm: &HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Xxh3 => Hasher::Xxh3(Box::new(xxhash_rust::xxh3::Xxh3::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Sha512(h) => h.update(data),
+            Hasher::Blake3(h) => {
+                h.update(data);
+            }
+            Hasher::Xxh3(h) => h.update(data),
+        };
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Sha256(h) => hex::encode(h.finalize()),
+            Hasher::Sha512(h) => hex::encode(h.finalize()),
+            Hasher::Blake3(h) => h.finalize().to_hex().to_string(),
+            Hasher::Xxh3(h) => format!("{:032x}", h.digest128()),
+        }
+    }
+}
+
+/// Calculate hashes of a file for every algorithm in the list, reading the file only once and
+/// in chunks instead of reading the whole file into memory.
+/// Files to be normalised must be read completely.
+pub fn file_hashes(
+    f: &str,
+    algorithms: &[HashAlgorithm],
+    normalise: Option<&Normalisation>,
+) -> Result<Vec<String>, io::Error> {
+    let mut hashers: Vec<Hasher> = algorithms.iter().map(Hasher::new).collect();
+
+    if let Some(norm) = normalise {
+        let raw = fs::read(f)?;
+        let content = normalise_content(norm, &raw);
+        for h in hashers.iter_mut() {
+            h.update(&content);
+        }
+        return Ok(hashers.into_iter().map(|h| h.finalize()).collect());
+    }
+
+    let mut file = fs::File::open(f)?;
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+
+    loop {
+        let len = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for h in hashers.iter_mut() {
+            h.update(&buffer[..len]);
+        }
+    }
+
+    Ok(hashers.into_iter().map(|h| h.finalize()).collect())
+}
+
+/// Calculate hashes of data already in memory, e.g. a page fetched via HTTP
+pub fn content_hashes(
+    data: &[u8],
+    algorithms: &[HashAlgorithm],
+    normalise: Option<&Normalisation>,
+) -> Vec<String> {
+    let normalised;
+    let content = match normalise {
+        Some(norm) => {
+            normalised = normalise_content(norm, data);
+            &normalised[..]
+        }
+        None => data,
+    };
+
+    algorithms
+        .iter()
+        .map(|a| {
+            let mut h = Hasher::new(a);
+            h.update(content);
+            h.finalize()
+        })
+        .collect()
+}
+
+fn normalise_content(norm: &Normalisation, raw: &[u8]) -> Vec<u8> {
+    // Only use content of the first element found, e.g. <main> or <article>
+    let mut content = raw;
+    for re in &norm.element_list {
+        if let Some(element) = re.captures(raw).and_then(|c| c.get(1)) {
+            content = element.as_bytes();
+            break;
+        }
+    }
+
+    let mut result = content.to_vec();
+    for re in &norm.strip_list {
+        result = re.replace_all(&result, &b""[..]).into_owned();
+    }
+    result
+}
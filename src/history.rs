@@ -0,0 +1,88 @@
+use crate::config;
+use crate::sqlite3;
+
+use chrono::TimeZone;
+use simple_error::bail;
+use std::error::Error;
+use url::Url;
+
+/// Show submission history of URLs matching filter between since and until. The filter can be a
+/// URL, a URL prefix ending with '*' or a path prefix on the host of base_url. Without filter,
+/// the history of all URLs is shown.
+pub fn show(
+    cfg: &config::Configuration,
+    db: &rusqlite::Connection,
+    filter: Option<&str>,
+    since: Option<&str>,
+    until: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let (url, prefix) = match filter.map(|f| f.trim()) {
+        None | Some("") => (String::new(), true),
+        Some(f) if f.starts_with('/') => {
+            let base = Url::parse(&cfg.base_url)?;
+            (base.join(f.trim_end_matches('*'))?.to_string(), true)
+        }
+        Some(f) => match f.strip_suffix('*') {
+            Some(v) => (v.to_string(), true),
+            None => (f.to_string(), false),
+        },
+    };
+    let since = match since {
+        Some(v) => Some(parse_time(v)?),
+        None => None,
+    };
+    let until = match until {
+        Some(v) => Some(parse_time(v)?),
+        None => None,
+    };
+
+    for entry in sqlite3::history_get(db, &url, prefix, since, until)? {
+        let timestamp = format_time(entry.timestamp);
+        let status = match entry.status {
+            Some(v) => v.to_string(),
+            None => "-".to_string(),
+        };
+        let reply = match (&entry.error, &entry.response) {
+            (Some(e), Some(r)) if !r.is_empty() => format!("{} ({})", e, r),
+            (Some(e), _) => e.clone(),
+            (None, Some(r)) => r.clone(),
+            (None, None) => String::new(),
+        };
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            timestamp,
+            entry.url,
+            if entry.deleted { "removed" } else { "updated" },
+            entry.endpoint,
+            entry.batch,
+            status,
+            reply.replace(['\n', '\r', '\t'], " ")
+        );
+    }
+    Ok(())
+}
+
+/// Format UNIX timestamp like the time of log messages
+pub fn format_time(timestamp: i64) -> String {
+    match chrono::Local.timestamp_opt(timestamp, 0).single() {
+        Some(v) => v.format("%Y-%m-%dT%H:%M:%S%z").to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+/// Parse RFC 3339 timestamp or date (midnight UTC) as UNIX timestamp
+fn parse_time(s: &str) -> Result<i64, Box<dyn Error>> {
+    if let Ok(v) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Ok(v.timestamp());
+    }
+    match chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+    {
+        Some(v) => Ok(chrono::Utc.from_utc_datetime(&v).timestamp()),
+        None => bail!(
+            "{} is neither a RFC 3339 timestamp nor a date (YYYY-MM-DD)",
+            s
+        ),
+    }
+}
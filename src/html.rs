@@ -0,0 +1,95 @@
+use crate::constants;
+
+use regex::Regex;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+/// Regular expressions to parse HTML files, compiled once when the configuration is read
+#[derive(Clone, Debug)]
+pub struct HtmlRegex {
+    pub head_end: Regex,
+    pub tag: Regex,
+    pub attribute: Regex,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HtmlInfo {
+    pub noindex: bool,
+    pub canonical: Option<String>,
+}
+
+pub fn build_regex() -> Result<HtmlRegex, Box<dyn Error>> {
+    Ok(HtmlRegex {
+        head_end: Regex::new(constants::HTML_HEAD_END_REGEX)?,
+        tag: Regex::new(constants::HTML_META_LINK_TAG_REGEX)?,
+        attribute: Regex::new(constants::HTML_ATTRIBUTE_REGEX)?,
+    })
+}
+
+pub fn parse_file(f: &str, re: &HtmlRegex) -> Result<HtmlInfo, Box<dyn Error>> {
+    let raw = fs::read(f)?;
+    let content = String::from_utf8_lossy(&raw);
+    Ok(parse(&content, re))
+}
+
+pub fn parse(content: &str, re: &HtmlRegex) -> HtmlInfo {
+    let mut result = HtmlInfo::default();
+
+    // Robots meta and canonical links are only valid in the head of the document
+    let head = match re.head_end.find(content) {
+        Some(m) => &content[..m.start()],
+        None => content,
+    };
+
+    for tag in re.tag.captures_iter(head) {
+        let attrs = parse_attributes(&tag[2], &re.attribute);
+        if tag[1].eq_ignore_ascii_case("meta") {
+            let name = attrs.get("name").map(|n| n.to_lowercase());
+            if name.as_deref() != Some("robots") {
+                continue;
+            }
+            if let Some(content) = attrs.get("content") {
+                result.noindex |= content
+                    .split(',')
+                    .map(|v| v.trim().to_lowercase())
+                    .any(|v| v == "noindex" || v == "none");
+            }
+        } else {
+            let is_canonical = match attrs.get("rel") {
+                Some(rel) => rel
+                    .split_whitespace()
+                    .any(|r| r.eq_ignore_ascii_case("canonical")),
+                None => false,
+            };
+            if is_canonical && result.canonical.is_none() {
+                result.canonical = attrs.get("href").map(|h| h.trim().to_string());
+            }
+        }
+    }
+
+    result
+}
+
+fn parse_attributes(raw: &str, attr_re: &Regex) -> HashMap<String, String> {
+    let mut result = HashMap::<String, String>::new();
+
+    for attr in attr_re.captures_iter(raw) {
+        let value = attr
+            .get(2)
+            .or_else(|| attr.get(3))
+            .or_else(|| attr.get(4))
+            .map(|v| v.as_str())
+            .unwrap_or_default();
+        result.insert(attr[1].to_lowercase(), html_unescape(value));
+    }
+    result
+}
+
+pub fn html_unescape(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
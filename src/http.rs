@@ -1,9 +1,13 @@
+use crate::config;
 use crate::constants;
 
-use log::debug;
+use log::{debug, warn};
+use rand::Rng;
 use reqwest::StatusCode;
 use simple_error::bail;
 use std::error::Error;
+use std::fs;
+use std::thread;
 use std::time::Duration;
 
 pub fn build_client(timeout_sec: u64) -> Result<reqwest::blocking::Client, Box<dyn Error>> {
@@ -17,10 +21,6 @@ pub fn build_client(timeout_sec: u64) -> Result<reqwest::blocking::Client, Box<d
         "Accept",
         reqwest::header::HeaderValue::from_static("application/json"),
     );
-    headers.insert(
-        "Content-Type",
-        reqwest::header::HeaderValue::from_static("application/json; charset=utf-8"),
-    );
 
     let http_client_builder = reqwest::blocking::ClientBuilder::new()
         .user_agent(constants::generate_user_agent())
@@ -35,50 +35,261 @@ pub fn build_client(timeout_sec: u64) -> Result<reqwest::blocking::Client, Box<d
     Ok(http_client)
 }
 
+/// HTTP status and start of the body of the last response to a request
+#[derive(Clone, Debug, Default)]
+pub struct Reply {
+    pub status: Option<u16>,
+    pub body: String,
+}
+
 pub fn post(
     http_client: &mut reqwest::blocking::Client,
     url: &str,
     data: String,
+    retry: &config::RetryConfiguration,
+    reply: &mut Reply,
 ) -> Result<(), Box<dyn Error>> {
     debug!("Sending HTTP POST request to {}", &url);
-    let response = http_client.post(url).body(data).send()?;
-    match response.status() {
-        StatusCode::OK | StatusCode::ACCEPTED => Ok(()),
-        StatusCode::BAD_REQUEST => {
-            bail!("invalid format");
-        }
-        StatusCode::FORBIDDEN => {
-            bail!("key not valid, e.g. key not found, file found but key not in the file");
-        }
-        StatusCode::UNPROCESSABLE_ENTITY => {
-            bail!("URLs which don’t belong to the host or the key is not matching the schema in the protocol");
-        }
-        StatusCode::TOO_MANY_REQUESTS => {
-            bail!("too many requests (potential Spam)")
+    submit(
+        || {
+            http_client
+                .post(url)
+                .header(
+                    reqwest::header::CONTENT_TYPE,
+                    "application/json; charset=utf-8",
+                )
+                .body(data.clone())
+        },
+        retry,
+        reply,
+    )
+}
+
+/// Submit a single URL using the GET form of the IndexNow API, the URL and the key are part of
+/// the query of url
+pub fn submit_get(
+    http_client: &mut reqwest::blocking::Client,
+    url: &str,
+    retry: &config::RetryConfiguration,
+    reply: &mut Reply,
+) -> Result<(), Box<dyn Error>> {
+    debug!("Sending HTTP GET request to {}", &url);
+    submit(|| http_client.get(url), retry, reply)
+}
+
+/// Send a submission to an IndexNow endpoint, retrying on temporary failures
+fn submit<F>(
+    request: F,
+    retry: &config::RetryConfiguration,
+    reply: &mut Reply,
+) -> Result<(), Box<dyn Error>>
+where
+    F: Fn() -> reqwest::blocking::RequestBuilder,
+{
+    let mut attempt: u32 = 0;
+    let mut waited: u64 = 0;
+
+    loop {
+        *reply = Reply::default();
+        let (reason, retry_after) = match request().send() {
+            Ok(response) => {
+                let status = response.status();
+                let retry_after = parse_retry_after(&response);
+                let body = response.text().unwrap_or_default();
+                *reply = Reply {
+                    status: Some(status.as_u16()),
+                    body: body
+                        .trim()
+                        .chars()
+                        .take(constants::REPLY_BODY_EXCERPT)
+                        .collect(),
+                };
+
+                match status {
+                    StatusCode::OK | StatusCode::ACCEPTED => return Ok(()),
+                    StatusCode::BAD_REQUEST => {
+                        bail!("invalid format");
+                    }
+                    StatusCode::FORBIDDEN => {
+                        bail!(
+                            "key not valid, e.g. key not found, file found but key not in the file"
+                        );
+                    }
+                    StatusCode::UNPROCESSABLE_ENTITY => {
+                        bail!("URLs which don’t belong to the host or the key is not matching the schema in the protocol");
+                    }
+                    StatusCode::TOO_MANY_REQUESTS => (
+                        "too many requests (potential Spam)".to_string(),
+                        retry_after,
+                    ),
+                    StatusCode::SERVICE_UNAVAILABLE => {
+                        ("service unavailable".to_string(), retry_after)
+                    }
+                    status if status.is_server_error() => {
+                        (format!("unexpected HTTP status {}", status), None)
+                    }
+                    _ => {
+                        let reason = status.canonical_reason().unwrap_or("???");
+                        bail!("unexpected HTTP status {} {}", status, reason);
+                    }
+                }
+            }
+            Err(e) => {
+                if !e.is_timeout() && !e.is_connect() {
+                    return Err(Box::new(e));
+                }
+                (e.to_string(), None)
+            }
+        };
+
+        if attempt >= retry.max_retries {
+            bail!("{} - giving up after {} retries", reason, attempt);
         }
-        _ => {
-            let reason = response.status().canonical_reason().unwrap_or("???");
-            bail!("unexpected HTTP status {} {}", response.status(), reason);
+
+        // Honour Retry-After if the server sent one, use exponential backoff otherwise
+        let delay = match retry_after {
+            Some(v) => v,
+            None => backoff_delay(retry, attempt),
+        };
+        if waited + delay > retry.max_total {
+            bail!(
+                "{} - retrying after {} seconds would exceed the total retry time of {} seconds",
+                reason,
+                delay,
+                retry.max_total
+            );
         }
+
+        attempt += 1;
+        warn!(
+            "{} - retrying in {} seconds (attempt {} of {})",
+            reason, delay, attempt, retry.max_retries
+        );
+        thread::sleep(Duration::from_secs(delay));
+        waited += delay;
+    }
+}
+
+/// HTTP status of url, a GET request is sent if the server doesn't allow HEAD requests
+pub fn head(
+    http_client: &mut reqwest::blocking::Client,
+    url: &str,
+) -> Result<StatusCode, Box<dyn Error>> {
+    debug!("Sending HTTP HEAD request to {}", &url);
+    let response = http_client
+        .head(url)
+        .header(reqwest::header::ACCEPT, "*/*")
+        .send()?;
+    if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return Ok(response.status());
     }
+
+    // Some servers don't support HEAD requests, only the status of the GET request is used
+    debug!(
+        "HEAD request is not allowed, sending HTTP GET request to {}",
+        &url
+    );
+    let response = http_client
+        .get(url)
+        .header(reqwest::header::ACCEPT, "*/*")
+        .send()?;
+    Ok(response.status())
+}
+
+fn backoff_delay(retry: &config::RetryConfiguration, attempt: u32) -> u64 {
+    let delay = retry
+        .initial_delay
+        .saturating_mul(1 << attempt.min(16))
+        .min(retry.max_delay);
+
+    // Add jitter to avoid multiple clients retrying at the same time
+    rand::thread_rng().gen_range(delay / 2..=delay)
+}
+
+fn parse_retry_after(response: &reqwest::blocking::Response) -> Option<u64> {
+    let value = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?;
+
+    // Retry-After can be a number of seconds or a HTTP date - https://www.rfc-editor.org/rfc/rfc9110#field.retry-after
+    if let Ok(v) = value.trim().parse::<u64>() {
+        return Some(v);
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let delta = date.timestamp() - chrono::Utc::now().timestamp();
+    Some(delta.max(0) as u64)
 }
 
-/*
 pub fn get(
     http_client: &mut reqwest::blocking::Client,
     url: &str,
-) -> Result<String, Box<dyn Error>> {
-    debug!("GET {}", &url);
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    debug!("Sending HTTP GET request to {}", &url);
 
-    let response = http_client.get(url).send()?;
-    if response.status() != reqwest::StatusCode::OK {
+    let response = http_client
+        .get(url)
+        .header(reqwest::header::ACCEPT, "*/*")
+        .send()?;
+    if response.status() != StatusCode::OK {
         bail!(
             "HTTP connection returned HTTP status code \"{}\" instead of \"200 OK\"",
             response.status()
         );
     }
 
-    let reply = response.text()?;
-    Ok(reply)
+    let reply = response.bytes()?;
+    Ok(reply.to_vec())
+}
+
+/// Like read_location, but a missing file or a resource the web server reports as
+/// unavailable (HTTP status 4xx) is returned as None
+pub fn read_location_optional(
+    http_client: &mut reqwest::blocking::Client,
+    location: &str,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    if !is_url(location) {
+        debug!("Reading {}", location);
+        return match fs::read(location) {
+            Ok(v) => Ok(Some(v)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        };
+    }
+
+    debug!("Sending HTTP GET request to {}", &location);
+    let response = http_client
+        .get(location)
+        .header(reqwest::header::ACCEPT, "*/*")
+        .send()?;
+    let status = response.status();
+    if status.is_client_error() {
+        debug!("{} returned HTTP status {}", location, status);
+        return Ok(None);
+    }
+    if status != StatusCode::OK {
+        bail!(
+            "HTTP connection returned HTTP status code \"{}\" instead of \"200 OK\"",
+            status
+        );
+    }
+    Ok(Some(response.bytes()?.to_vec()))
+}
+
+pub fn is_url(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+/// Read data from a file or, if location is a HTTP(S) URL, from a web server
+pub fn read_location(
+    http_client: &mut reqwest::blocking::Client,
+    location: &str,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    if is_url(location) {
+        get(http_client, location)
+    } else {
+        debug!("Reading {}", location);
+        Ok(fs::read(location)?)
+    }
 }
-*/
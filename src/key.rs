@@ -0,0 +1,85 @@
+use crate::config;
+use crate::constants;
+use crate::http;
+
+use log::{debug, info};
+use rand::Rng;
+use simple_error::bail;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use url::Url;
+
+/// Generate a new key. If a directory is given, the key file is stored in it.
+pub fn generate(dir: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let mut rng = rand::thread_rng();
+    let key: String = (0..constants::KEY_LENGTH)
+        .map(|_| {
+            let i = rng.gen_range(0..constants::KEY_CHARACTERS.len());
+            constants::KEY_CHARACTERS[i] as char
+        })
+        .collect();
+
+    if let Some(d) = dir {
+        let file = Path::new(d).join(format!("{}.txt", key));
+        info!("Writing key file {}", file.display());
+        fs::write(&file, format!("{}\n", key))?;
+    }
+    Ok(key)
+}
+
+/// URL of the key file, search engines will look for it at the root of the host if no key
+/// location is configured
+pub fn location(cfg: &config::Configuration) -> Result<String, Box<dyn Error>> {
+    match &cfg.key_location {
+        Some(v) => Ok(v.clone()),
+        None => Ok(Url::parse(&cfg.base_url)?
+            .join(&format!("/{}.txt", cfg.key))?
+            .to_string()),
+    }
+}
+
+/// Check format of the key and the content of the key file served at the key location
+pub fn verify(cfg: &config::Configuration) -> Result<(), Box<dyn Error>> {
+    // Key format - https://www.indexnow.org/documentation
+    let len = cfg.key.len();
+    if !(constants::KEY_MIN_LENGTH..=constants::KEY_MAX_LENGTH).contains(&len) {
+        bail!(
+            "key must have between {} and {} characters, but it has {} characters",
+            constants::KEY_MIN_LENGTH,
+            constants::KEY_MAX_LENGTH,
+            len
+        );
+    }
+    if !cfg
+        .key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        bail!("key must only contain the characters a-z, A-Z, 0-9 and -");
+    }
+
+    let location = location(cfg)?;
+    if let Some(v) = &cfg.key_location {
+        let key_url = Url::parse(v)?;
+        if key_url.host_str() != Some(cfg.host.as_str()) {
+            bail!(
+                "key location {} is not located on the host {}",
+                location,
+                cfg.host
+            );
+        }
+    }
+
+    debug!("Fetching key file from {}", location);
+    let mut http_client = http::build_client(constants::DEFAULT_TIMEOUT)?;
+    let content = match http::get(&mut http_client, &location) {
+        Ok(v) => v,
+        Err(e) => bail!("Can't fetch key file {}: {}", location, e),
+    };
+    if String::from_utf8_lossy(&content).trim() != cfg.key {
+        bail!("key file {} doesn't contain the configured key", location);
+    }
+    info!("Key file {} contains the configured key", location);
+    Ok(())
+}
@@ -1,5 +1,7 @@
-pub fn init(level: log::LevelFilter) -> Result<(), fern::InitError> {
-    fern::Dispatch::new()
+/// Initialise logging, log messages are written to stderr if stdout is used for the output of
+/// reports
+pub fn init(level: log::LevelFilter, stderr: bool) -> Result<(), fern::InitError> {
+    let dispatch = fern::Dispatch::new()
         .format(|logout, logmsg, logrecord| {
             logout.finish(format_args!(
                 "{:<6}: {} {}",
@@ -8,8 +10,12 @@ pub fn init(level: log::LevelFilter) -> Result<(), fern::InitError> {
                 logmsg
             ))
         })
-        .level(level)
-        .chain(std::io::stdout())
-        .apply()?;
+        .level(level);
+
+    if stderr {
+        dispatch.chain(std::io::stderr()).apply()?;
+    } else {
+        dispatch.chain(std::io::stdout()).apply()?;
+    }
     Ok(())
 }
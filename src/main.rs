@@ -1,33 +1,40 @@
-use getopts::Options;
 use log::{debug, error, info};
 use std::env;
+use std::error::Error;
 use std::process;
 
+mod cli;
+mod commands;
 mod config;
 mod constants;
+mod git;
+mod hash;
+mod history;
+mod html;
 mod http;
+mod key;
 mod logging;
 mod payload;
+mod robots;
 mod scan;
+mod sitemap;
 mod sqlite3;
+mod status;
 mod usage;
+#[cfg(target_os = "linux")]
+mod watch;
 
 fn main() {
     let argv: Vec<String> = env::args().collect();
-    let mut options = Options::new();
     let mut log_level = log::LevelFilter::Info;
-    let mut purge_old = false;
-    let mut dry_run = false;
-
-    options.optflag("D", "debug", "Enable debug output");
-    options.optopt("c", "config", "Path to configuration file", "config_file");
-    options.optflag("h", "help", "Show help text");
-    options.optflag("n", "dry-run", "Dry run mode");
-    options.optflag("p", "purge", "Remove stale database entries");
-    options.optflag("v", "version", "Show version information");
-    options.optflag("q", "--quiet", "Quiet operation");
-
-    let opts = match options.parse(&argv[1..]) {
+
+    // Invocations without a command are handled as run
+    let (mut command, args) = match argv.get(1).and_then(|a| cli::find(a)) {
+        Some(c) => (c.name, &argv[2..]),
+        None => (cli::LEGACY, &argv[1..]),
+    };
+
+    let opts = match cli::options(command).parse(args) {
         Ok(v) => v,
         Err(e) => {
             eprintln!("Error: Can't parse command line arguments ({})", e);
@@ -36,7 +43,10 @@ fn main() {
     };
 
     if opts.opt_present("h") {
-        usage::show_usage();
+        match cli::find(command) {
+            Some(c) => usage::show_command_usage(c),
+            None => usage::show_usage(),
+        };
         process::exit(0);
     }
 
@@ -45,6 +55,22 @@ fn main() {
         process::exit(0);
     }
 
+    if command == "help" {
+        match opts.free.first() {
+            Some(name) => match cli::find(name) {
+                Some(c) => usage::show_command_usage(c),
+                None => {
+                    eprintln!("Error: Unknown command {}", name);
+                    println!();
+                    usage::show_usage();
+                    process::exit(1);
+                }
+            },
+            None => usage::show_usage(),
+        };
+        process::exit(0);
+    }
+
     if opts.opt_present("q") {
         log_level = log::LevelFilter::Warn;
     }
@@ -53,26 +79,49 @@ fn main() {
         log_level = log::LevelFilter::Debug;
     }
 
-    if opts.opt_present("p") {
-        purge_old = true;
+    // Options of the legacy invocation are only defined for some commands
+    let flag = |name: &str| opts.opt_defined(name) && opts.opt_present(name);
+    let value = |name: &str| {
+        if opts.opt_defined(name) {
+            opts.opt_str(name)
+        } else {
+            None
+        }
     };
 
-    if opts.opt_present("n") {
-        dry_run = true;
+    let mut free = opts.free.clone();
+    if command == cli::LEGACY {
+        command = if flag("db-migrate") {
+            free = vec!["migrate".to_string()];
+            "db"
+        } else if flag("H") {
+            free = value("H").into_iter().collect();
+            "history"
+        } else if free.first().map(|c| c == "status").unwrap_or(false) {
+            free.remove(0);
+            "status"
+        } else {
+            "run"
+        };
     }
+    let usage_of = cli::find(command).expect("command is defined");
 
-    let config_file = match opts.opt_str("c") {
-        Some(v) => v,
-        None => {
-            eprintln!("Error: Configuration file is mandatory");
+    // Subcommands of db and key
+    let action = if command == "db" || command == "key" {
+        if free.is_empty() {
+            eprintln!("Error: Missing action for command {}", command);
             println!();
-            usage::show_usage();
+            usage::show_command_usage(usage_of);
             process::exit(1);
         }
+        free.remove(0)
+    } else {
+        String::new()
     };
 
-    // Initialise logging via fern
-    match logging::init(log_level) {
+    // Initialise logging via fern, reports are written to stdout
+    let report = !["run", "scan", "submit", "url"].contains(&command);
+    match logging::init(log_level, report) {
         Ok(_) => {}
         Err(e) => {
             eprintln!("Error: Unable to initialise logging - {}", e);
@@ -80,29 +129,36 @@ fn main() {
         }
     };
 
-    if opts.free.is_empty() {
-        eprintln!("Error: Missing path to HTML data is missing");
-        println!();
-        usage::show_usage();
-        process::exit(1);
-    }
-
-    if opts.free.len() > 1 {
-        eprintln!("Error: Only a single value for HTML data is allowed");
-        println!();
-        usage::show_usage();
-        process::exit(1);
+    // A new key doesn't need a configuration
+    if command == "key" && action == "generate" {
+        if free.len() > 1 {
+            eprintln!("Error: Only a single directory for the key file is allowed");
+            println!();
+            usage::show_command_usage(usage_of);
+            process::exit(1);
+        }
+        match key::generate(free.first().map(|d| d.as_str())) {
+            Ok(v) => println!("{}", v),
+            Err(e) => {
+                error!("Can't generate key: {}", e);
+                process::exit(1);
+            }
+        };
+        process::exit(0);
     }
 
-    let mut html_dir: String = opts.free[0].clone();
-    while html_dir.ends_with('/') {
-        html_dir.pop();
-    }
-    html_dir.push('/');
+    let config_file = match opts.opt_str("c") {
+        Some(v) => v,
+        None => {
+            eprintln!("Error: Configuration file is mandatory");
+            println!();
+            usage::show_command_usage(usage_of);
+            process::exit(1);
+        }
+    };
 
-    debug!("HTML data directory is at {}", html_dir);
     info!("Parsing configuration file {}", config_file);
-    let config = match config::parse_config_file(&config_file) {
+    let mut sites = match config::parse_config_file(&config_file) {
         Ok(v) => v,
         Err(e) => {
             error!("Can't read configuration from {}: {}", config_file, e);
@@ -110,42 +166,218 @@ fn main() {
         }
     };
 
-    debug!("Parsed configuration: {:?}", config);
-    debug!("Opening database connection to {}", config.database);
-    let mut db_handle = match sqlite3::open(&config.database) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Can't open databse file {}: {}", config.database, e);
+    if let Some(name) = opts.opt_str("s") {
+        sites.retain(|s| s.name == name);
+        if sites.is_empty() {
+            error!(
+                "Site {} not found in configuration file {}",
+                name, config_file
+            );
             process::exit(1);
         }
+    }
+
+    let usage_error = |msg: &str| -> ! {
+        eprintln!("Error: {}", msg);
+        println!();
+        usage::show_command_usage(usage_of);
+        process::exit(1);
     };
 
-    if let Some(fext_list) = config.file_extensions.clone() {
-        let _indexnow =
-            match scan::build_update_list(&html_dir, &mut db_handle, fext_list, purge_old, dry_run)
+    let dry_run = flag("n");
+    let failed = match command {
+        "run" | "scan" | "status" => {
+            if free.len() > 1 {
+                usage_error("Only a single value for HTML data is allowed, use roots in the configuration for multiple directories");
+            }
+            let run_opts = commands::RunOptions {
+                html_dir: free.first().map(|v| {
+                    let mut dir = v.clone();
+                    while dir.ends_with('/') {
+                        dir.pop();
+                    }
+                    dir.push('/');
+                    dir
+                }),
+                list: value("l"),
+                range: value("R"),
+                purge_old: flag("p"),
+                dry_run,
+                rehash: flag("r"),
+                watch: flag("w"),
+                update_hashes: flag("u"),
+            };
+            debug!("HTML data directory is at {:?}", run_opts.html_dir);
+
+            // Paths, lists and revisions belong to a single site
+            if sites.len() > 1
+                && (run_opts.html_dir.is_some()
+                    || run_opts.list.is_some()
+                    || run_opts.range.is_some()
+                    || run_opts.watch)
             {
-                Ok(v) => v,
-                Err(e) => {
-                    error!("Unable to build file list: {}", e);
-                    process::exit(1);
+                usage_error("Path to HTML data, list of files, revision range and watch mode can only be used for a single site, use --site to select it");
+            }
+
+            if command == "status" {
+                show_status(&sites, &run_opts, flag("json"))
+            } else {
+                for config in &sites {
+                    if let Err(e) = commands::check_site_options(config, &run_opts) {
+                        usage_error(&e.to_string());
+                    }
+                }
+                if command == "scan" {
+                    process_sites(&sites, |c| commands::scan_site(c, &run_opts))
+                } else {
+                    process_sites(&sites, |c| commands::run_site(c, &run_opts))
+                }
+            }
+        }
+        "submit" => {
+            if !free.is_empty() {
+                usage_error("submit doesn't accept arguments");
+            }
+            process_sites(&sites, |c| commands::submit_site(c, dry_run))
+        }
+        "url" => {
+            if free.is_empty() {
+                usage_error("Missing URL to submit");
+            }
+            if sites.len() > 1 {
+                usage_error(
+                    "URLs can only be submitted for a single site, use --site to select it",
+                );
+            }
+            process_sites(&sites, |c| commands::submit_urls(c, &free, dry_run))
+        }
+        "history" => {
+            if free.len() > 1 {
+                usage_error("Only a single URL is allowed");
+            }
+            let since = value("since");
+            let until = value("until");
+            process_sites(&sites, |c| {
+                commands::show_history(
+                    c,
+                    free.first().map(|f| f.as_str()),
+                    since.as_deref(),
+                    until.as_deref(),
+                )
+            })
+        }
+        "db" => match action.as_str() {
+            "export" | "import" => {
+                if free.len() != 1 {
+                    usage_error(&format!("db {} requires a single file", action));
+                }
+                if sites.len() > 1 {
+                    usage_error(&format!(
+                        "db {} can only be used for a single site, use --site to select it",
+                        action
+                    ));
+                }
+                if action == "export" {
+                    process_sites(&sites, |c| commands::db_export(c, &free[0]))
+                } else {
+                    process_sites(&sites, |c| commands::db_import(c, &free[0], dry_run))
+                }
+            }
+            "forget" => {
+                if free.is_empty() {
+                    usage_error("db forget requires at least one file or URL");
+                }
+                process_sites(&sites, |c| commands::db_forget(c, &free, dry_run))
+            }
+            "vacuum" | "migrate" => {
+                if !free.is_empty() {
+                    usage_error(&format!("db {} doesn't accept arguments", action));
                 }
+                if action == "vacuum" {
+                    process_sites(&sites, commands::db_vacuum)
+                } else {
+                    process_sites(&sites, |c| sqlite3::migrate_file(&c.database, dry_run))
+                }
+            }
+            _ => usage_error(&format!("Unknown action {} for command db", action)),
+        },
+        "key" => match action.as_str() {
+            "verify" => {
+                if !free.is_empty() {
+                    usage_error("key verify doesn't accept arguments");
+                }
+                process_sites(&sites, commands::key_verify)
+            }
+            _ => usage_error(&format!("Unknown action {} for command key", action)),
+        },
+        _ => usage_error(&format!("Unknown command {}", command)),
+    };
+
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+/// Run a command for all sites and return the number of failed sites
+fn process_sites<F>(sites: &[config::Configuration], mut f: F) -> usize
+where
+    F: FnMut(&config::Configuration) -> Result<(), Box<dyn Error>>,
+{
+    let mut failed: usize = 0;
+    let mut results = Vec::<(String, Result<(), Box<dyn Error>>)>::new();
+    for config in sites {
+        if sites.len() > 1 {
+            info!("Processing site {}", config.name);
+        }
+        let result = f(config);
+        if let Err(e) = &result {
+            error!("{}", e);
+            failed += 1;
+        }
+        results.push((config.name.clone(), result));
+    }
+
+    if sites.len() > 1 {
+        info!("Processed {} sites, {} sites failed", results.len(), failed);
+        for (name, result) in &results {
+            match result {
+                Ok(_) => info!("Site {}: OK", name),
+                Err(e) => error!("Site {}: {}", name, e),
             };
-        if _indexnow.is_empty() {
-            info!("List of updated files is empty");
-            process::exit(0);
         }
+    }
+    failed
+}
 
-        let __indexnow = payload::remove_excludes(&config.exclude_list, &_indexnow);
-        let indexnow = payload::massage_payload(&config.base_url, &html_dir, __indexnow);
-        match payload::process_payload(config, indexnow, dry_run) {
-            Ok(_) => {}
+/// Collect the status of all sites and print it, returns the number of unhealthy sites
+fn show_status(
+    sites: &[config::Configuration],
+    run_opts: &commands::RunOptions,
+    json: bool,
+) -> usize {
+    let mut failed: usize = 0;
+    let mut list = Vec::<status::SiteStatus>::new();
+    for config in sites {
+        match status::collect(config, &commands::site_roots(config, &run_opts.html_dir)) {
+            Ok(v) => {
+                if !v.is_healthy() {
+                    error!(
+                        "Site {}: integrity check or schema version of database failed",
+                        config.name
+                    );
+                    failed += 1;
+                }
+                list.push(v);
+            }
             Err(e) => {
-                error!("Submission failed: {}", e);
-                process::exit(1);
+                error!("Site {}: {}", config.name, e);
+                failed += 1;
             }
         };
-    } else {
-        error!("List of file extensions is empty");
-        process::exit(1);
     }
+    if let Err(e) = status::print(&list, json) {
+        error!("{}", e);
+        failed += 1;
+    }
+    failed
 }
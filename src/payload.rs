@@ -1,10 +1,33 @@
 use crate::config;
 use crate::constants;
+use crate::html;
 use crate::http;
+use crate::robots;
+use crate::scan;
+use crate::sqlite3;
 
-use log::{debug, info};
+use log::{debug, error, info, warn};
+use reqwest::StatusCode;
 use serde::Serialize;
+use simple_error::bail;
+use std::collections::HashSet;
 use std::error::Error;
+use std::path::Path;
+use url::Url;
+
+#[derive(Clone, Debug)]
+pub struct PendingUrl {
+    pub url: String,
+    pub file: Option<scan::Filehash>,
+    pub deleted: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct SubmitResult {
+    pub endpoint: String,
+    pub submitted: usize,
+    pub failed: usize,
+}
 
 #[derive(Serialize, Clone, Debug)]
 struct IndexNowData {
@@ -19,101 +42,1140 @@ struct IndexNowData {
 
 fn build_post_payload(
     cfg: &config::Configuration,
+    host: &str,
     list: Vec<String>,
 ) -> Result<String, Box<dyn Error>> {
-    let raw_payload = IndexNowData {
-        host: cfg.host.clone(),
-        key: cfg.key.clone(),
-        key_location: cfg.key_location.clone(),
-        url_list: list,
-    };
+    let raw_payload = build_dump_payload(cfg, host, list);
     let payload = serde_json::to_string(&raw_payload)?;
     Ok(payload)
 }
 
-fn build_dump_payload(cfg: &config::Configuration, list: Vec<String>) -> IndexNowData {
+/// URL of a submission of a single URL using the GET form of the IndexNow API
+fn build_get_url(
+    cfg: &config::Configuration,
+    endpoint: &str,
+    url: &str,
+) -> Result<String, Box<dyn Error>> {
+    let mut query = vec![("url", url), ("key", cfg.key.as_str())];
+    if let Some(v) = &cfg.key_location {
+        query.push(("keyLocation", v.as_str()));
+    }
+    Ok(Url::parse_with_params(endpoint, &query)?.to_string())
+}
+
+fn build_dump_payload(cfg: &config::Configuration, host: &str, list: Vec<String>) -> IndexNowData {
+    // Key location is only valid for the configured host, search engines will look for the key
+    // at the root of other hosts
+    let key_location = if host == cfg.host {
+        cfg.key_location.clone()
+    } else {
+        None
+    };
+
     IndexNowData {
-        host: cfg.host.clone(),
+        host: host.to_string(),
         key: cfg.key.clone(),
-        key_location: cfg.key_location.clone(),
+        key_location,
         url_list: list,
     }
 }
 
-pub fn massage_payload(base_url: &str, html_dir: &str, list: Vec<String>) -> Vec<String> {
-    let mut result = Vec::<String>::new();
-    for entry in list {
-        result.push(entry.replacen(html_dir, base_url, 1));
+/// Apply rewrite rules in the order of the configuration
+pub fn rewrite_url(rules: &[(regex::Regex, String)], url: &str) -> String {
+    let mut result = url.to_string();
+    for (re, replacement) in rules {
+        let rewritten = re.replace(&result, replacement.as_str()).to_string();
+        if rewritten != result {
+            debug!(
+                "Rewriting {} to {} because it matches '{:?}'",
+                result, rewritten, re
+            );
+            result = rewritten;
+        }
     }
     result
 }
 
-pub fn remove_excludes(excludes: &[regex::Regex], list: &[String]) -> Vec<String> {
-    let mut trimmed: Vec<String> = list.to_vec();
+fn url_host(url: &str) -> Option<String> {
+    let parsed = Url::parse(url).ok()?;
+    parsed.host_str().map(|h| h.to_string())
+}
+
+pub fn is_on_host(cfg: &config::Configuration, url: &str) -> bool {
+    url_host(url).as_deref() == Some(cfg.host.as_str())
+}
+
+pub fn massage_payload(
+    base_url: &str,
+    html_dir: &str,
+    file: &str,
+    mapping: &config::UrlMapping,
+) -> Result<String, Box<dyn Error>> {
+    let mut path = match file.strip_prefix(html_dir) {
+        Some(v) => v.to_string(),
+        None => bail!("{} is not located below {}", file, html_dir),
+    };
+    let mut clean = false;
+
+    let (dir, fname) = match path.rfind('/') {
+        Some(i) => (&path[..=i], &path[i + 1..]),
+        None => ("", path.as_str()),
+    };
+
+    // blog/post/index.html -> blog/post/
+    if mapping.directory_index.iter().any(|idx| idx == fname) {
+        debug!("Mapping directory index {} to {}", path, dir);
+        path = dir.to_string();
+        clean = true;
+    } else if let Some((stem, ext)) = fname.rsplit_once('.') {
+        // about.html -> about
+        if !stem.is_empty()
+            && mapping
+                .strip_extensions
+                .iter()
+                .any(|e| e.to_lowercase() == ext.to_lowercase())
+        {
+            let stripped = format!("{}{}", dir, stem);
+            debug!("Stripping extension from {} -> {}", path, stripped);
+            path = stripped;
+            clean = true;
+        }
+    }
 
-    trimmed.retain(|entry| {
-        let mut exclude = false;
-        for re in excludes {
-            if re.is_match(entry) {
-                debug!("Rmoving '{}' because it matches '{:?}'", entry, re);
-                exclude = true;
-                break;
+    if clean {
+        match mapping.trailing_slash {
+            config::TrailingSlash::Keep => {}
+            config::TrailingSlash::Always => {
+                if !path.is_empty() && !path.ends_with('/') {
+                    path.push('/');
+                }
             }
+            config::TrailingSlash::Never => {
+                while path.ends_with('/') {
+                    path.pop();
+                }
+            }
+        };
+    }
+
+    // Encode every path segment, file names can contain characters like space, '#', '?' or '%'
+    let mut url = Url::parse(base_url)?;
+    match url.path_segments_mut() {
+        Ok(mut segments) => {
+            segments.pop_if_empty();
+            segments.extend(path.split('/'));
         }
-        !exclude
-    });
+        Err(_) => bail!("{} can't be used as base URL", base_url),
+    };
+    Ok(url.to_string())
+}
+
+/// Split list of changed and deleted files into files to submit and files matching the exclude list
+pub fn remove_excludes(
+    excludes: &[regex::Regex],
+    list: scan::UpdateList,
+) -> (scan::UpdateList, scan::UpdateList) {
+    let (excluded_changed, changed): (Vec<scan::Filehash>, Vec<scan::Filehash>) = list
+        .changed
+        .into_iter()
+        .partition(|entry| is_excluded(excludes, &entry.file));
+    let (excluded_deleted, deleted): (Vec<String>, Vec<String>) = list
+        .deleted
+        .into_iter()
+        .partition(|entry| is_excluded(excludes, entry));
 
+    let trimmed = scan::UpdateList {
+        changed,
+        deleted,
+        refreshed: Vec::new(),
+    };
     debug!("List after removal of excludes: {:?}", trimmed);
-    trimmed
+    (
+        trimmed,
+        scan::UpdateList {
+            changed: excluded_changed,
+            deleted: excluded_deleted,
+            refreshed: Vec::new(),
+        },
+    )
+}
+
+pub fn is_excluded(excludes: &[regex::Regex], entry: &str) -> bool {
+    for re in excludes {
+        if re.is_match(entry) {
+            debug!("Removing '{}' because it matches '{:?}'", entry, re);
+            return true;
+        }
+    }
+    false
+}
+
+/// Build list of URLs to submit from changed and deleted files.
+/// Excluded files will never be submitted, so the database can be updated right away for
+/// them, they are returned as second element together with files with unchanged content.
+fn build_pending_list(
+    cfg: &config::Configuration,
+    root: &config::HtmlRoot,
+    mut list: scan::UpdateList,
+) -> (Vec<PendingUrl>, scan::UpdateList) {
+    let refreshed = std::mem::take(&mut list.refreshed);
+    let (trimmed, mut excluded) = remove_excludes(&cfg.exclude_list, list);
+    excluded.refreshed = refreshed;
+    let mut pending = Vec::<PendingUrl>::new();
+    let mut seen_urls = HashSet::<String>::new();
+
+    for entry in trimmed.changed {
+        let url = match file_to_url(cfg, root, &entry.file) {
+            Some(v) => v,
+            None => continue,
+        };
+
+        let url = match apply_html_info(cfg, &entry.file, url) {
+            Some(v) => v,
+            None => {
+                // Never submitted, so the hash can be stored right away
+                excluded.changed.push(entry);
+                continue;
+            }
+        };
+
+        // Several pages can point to the same canonical URL
+        if !seen_urls.insert(url.clone()) {
+            debug!("{} is already in the list of pending URLs", url);
+            excluded.changed.push(entry);
+            continue;
+        }
+
+        pending.push(PendingUrl {
+            url,
+            file: Some(entry),
+            deleted: false,
+        });
+    }
+    for entry in trimmed.deleted {
+        if let Some(url) = file_to_url(cfg, root, &entry) {
+            pending.push(PendingUrl {
+                url,
+                file: Some(scan::Filehash {
+                    file: entry,
+                    hash: String::new(),
+                    algorithm: cfg.hash_algorithm,
+                    normalisation: None,
+                    meta: None,
+                }),
+                deleted: true,
+            });
+        }
+    }
+
+    debug!(
+        "{} URLs pending for submission, {} files excluded",
+        pending.len(),
+        excluded.changed.len() + excluded.deleted.len()
+    );
+    (pending, excluded)
+}
+
+/// Build list of URLs to submit from lists of changed and deleted files, e.g. of a git diff.
+/// Files are not stored in the database, so excluded files are just dropped.
+pub fn build_path_pending_list(
+    cfg: &config::Configuration,
+    root: &config::HtmlRoot,
+    changed: Vec<String>,
+    deleted: Vec<String>,
+) -> Vec<PendingUrl> {
+    let mut pending = Vec::<PendingUrl>::new();
+    let mut seen_urls = HashSet::<String>::new();
+
+    for entry in changed {
+        if is_excluded(&cfg.exclude_list, &entry) {
+            continue;
+        }
+        let url = match file_to_url(cfg, root, &entry) {
+            Some(v) => v,
+            None => continue,
+        };
+        let url = match apply_html_info(cfg, &entry, url) {
+            Some(v) => v,
+            None => continue,
+        };
+        if !seen_urls.insert(url.clone()) {
+            debug!("{} is already in the list of pending URLs", url);
+            continue;
+        }
+        pending.push(PendingUrl {
+            url,
+            file: None,
+            deleted: false,
+        });
+    }
+    for entry in deleted {
+        if is_excluded(&cfg.exclude_list, &entry) {
+            continue;
+        }
+        if let Some(url) = file_to_url(cfg, root, &entry) {
+            pending.push(PendingUrl {
+                url,
+                file: None,
+                deleted: true,
+            });
+        }
+    }
+
+    debug!("{} URLs pending for submission", pending.len());
+    pending
+}
+
+/// Honour robots meta tag and canonical link of HTML files. Returns the URL to submit or None
+/// if the page should not be submitted at all.
+fn apply_html_info(cfg: &config::Configuration, file: &str, url: String) -> Option<String> {
+    let re = match &cfg.html.regex {
+        Some(v) => v,
+        None => return Some(url),
+    };
+    if !scan::match_extension_list(Path::new(file), &cfg.html.extensions) {
+        return Some(url);
+    }
+
+    let info = match html::parse_file(file, re) {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("Can't parse HTML file {}: {}", file, e);
+            return Some(url);
+        }
+    };
+
+    if cfg.html.noindex && info.noindex {
+        debug!(
+            "Removing '{}' because the robots meta tag of {} contains noindex",
+            url, file
+        );
+        return None;
+    }
+
+    if !cfg.html.canonical {
+        return Some(url);
+    }
+    let canonical = match info.canonical {
+        Some(v) => v,
+        None => return Some(url),
+    };
+
+    // Canonical link can be relative to the URL of the page
+    let parsed = match Url::parse(&url).and_then(|base| base.join(&canonical)) {
+        Ok(v) => v,
+        Err(e) => {
+            warn!(
+                "Can't parse canonical link {} of {}: {} - using {}",
+                canonical, file, e, url
+            );
+            return Some(url);
+        }
+    };
+    if parsed.as_str() == url {
+        return Some(url);
+    }
+
+    if parsed.host_str() != Some(cfg.host.as_str()) {
+        debug!(
+            "Removing '{}' because its canonical URL {} is not located on {}",
+            url, parsed, cfg.host
+        );
+        return None;
+    }
+
+    debug!("Replacing '{}' by its canonical URL {}", url, parsed);
+    Some(parsed.to_string())
+}
+
+/// Map file to URL and apply rewrite rules. Invalid URLs are logged and skipped, because a
+/// single invalid URL will cause the rejection of the whole batch.
+fn file_to_url(cfg: &config::Configuration, root: &config::HtmlRoot, file: &str) -> Option<String> {
+    let mapped = match massage_payload(&root.base_url, &root.path, file, &cfg.url_mapping) {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("Can't build URL for {}: {} - skipping", file, e);
+            return None;
+        }
+    };
+    let rewritten = rewrite_url(&cfg.rewrite_list, &mapped);
+    validate_url(&rewritten, file)
+}
+
+fn validate_url(url: &str, source: &str) -> Option<String> {
+    let parsed = match Url::parse(url) {
+        Ok(v) => v,
+        Err(e) => {
+            warn!(
+                "{} (from {}) is not a valid URL: {} - skipping",
+                url, source, e
+            );
+            return None;
+        }
+    };
+    if parsed.host_str().is_none() {
+        warn!("{} (from {}) has no host part - skipping", url, source);
+        return None;
+    }
+    Some(parsed.to_string())
+}
+
+/// Build list of URLs to submit from changed and deleted URLs of a sitemap. Like
+/// build_pending_list, excluded URLs are returned as second element.
+fn build_sitemap_pending_list(
+    cfg: &config::Configuration,
+    mut list: scan::UpdateList,
+) -> (Vec<PendingUrl>, scan::UpdateList) {
+    let refreshed = std::mem::take(&mut list.refreshed);
+    let (mut trimmed, mut excluded) = remove_excludes(&cfg.exclude_list, list);
+    excluded.refreshed = refreshed;
+    let mut pending = Vec::<PendingUrl>::new();
+
+    // URLs of other hosts can't be submitted with the key of the site
+    let on_host = |url: &str| {
+        if is_on_host(cfg, url) {
+            return true;
+        }
+        warn!(
+            "{} (from sitemap) does not belong to the host {} - skipping",
+            url, cfg.host
+        );
+        false
+    };
+    let (changed, mut other): (Vec<scan::Filehash>, Vec<scan::Filehash>) = trimmed
+        .changed
+        .into_iter()
+        .partition(|entry| on_host(&entry.file));
+    excluded.changed.append(&mut other);
+    let (deleted, mut other): (Vec<String>, Vec<String>) = trimmed
+        .deleted
+        .into_iter()
+        .partition(|entry| on_host(entry));
+    excluded.deleted.append(&mut other);
+    trimmed.changed = changed;
+    trimmed.deleted = deleted;
+
+    for entry in trimmed.changed {
+        let rewritten = rewrite_url(&cfg.rewrite_list, &entry.file);
+        if let Some(url) = validate_url(&rewritten, &entry.file) {
+            pending.push(PendingUrl {
+                url,
+                file: Some(entry),
+                deleted: false,
+            });
+        }
+    }
+    for entry in trimmed.deleted {
+        let rewritten = rewrite_url(&cfg.rewrite_list, &entry);
+        if let Some(url) = validate_url(&rewritten, &entry) {
+            pending.push(PendingUrl {
+                url,
+                file: Some(scan::Filehash {
+                    file: entry,
+                    hash: String::new(),
+                    algorithm: cfg.hash_algorithm,
+                    normalisation: None,
+                    meta: None,
+                }),
+                deleted: true,
+            });
+        }
+    }
+
+    debug!(
+        "{} URLs pending for submission, {} URLs excluded",
+        pending.len(),
+        excluded.changed.len() + excluded.deleted.len()
+    );
+    (pending, excluded)
+}
+
+/// Remove URLs disallowed by robots.txt, these URLs are returned as second element.
+/// Only URLs located on the host of base_url are checked.
+fn remove_robots_disallowed(
+    cfg: &config::Configuration,
+    robots: &robots::Robots,
+    list: Vec<PendingUrl>,
+) -> (Vec<PendingUrl>, Vec<PendingUrl>) {
+    list.into_iter().partition(|entry| {
+        let parsed = match Url::parse(&entry.url) {
+            Ok(v) => v,
+            Err(_) => return true,
+        };
+        if parsed.host_str() != Some(cfg.host.as_str()) {
+            return true;
+        }
+
+        let path = match parsed.query() {
+            Some(q) => format!("{}?{}", parsed.path(), q),
+            None => parsed.path().to_string(),
+        };
+        if robots.is_allowed(&path) {
+            return true;
+        }
+        debug!(
+            "Removing '{}' because it is disallowed by robots.txt",
+            entry.url
+        );
+        false
+    })
 }
 
-pub fn process_payload(
-    cfg: config::Configuration,
+/// Only keep URLs of deleted files if the server reports them as gone (HTTP 404 or 410).
+/// Otherwise the file stays in the database and will be checked again on the next run.
+fn verify_deleted(list: Vec<PendingUrl>) -> Result<Vec<PendingUrl>, Box<dyn Error>> {
+    let mut result = Vec::<PendingUrl>::new();
+    let mut http_client = http::build_client(constants::DEFAULT_TIMEOUT)?;
+
+    for entry in list {
+        if !entry.deleted {
+            result.push(entry);
+            continue;
+        }
+
+        match http::head(&mut http_client, &entry.url) {
+            Ok(StatusCode::NOT_FOUND) | Ok(StatusCode::GONE) => {
+                debug!(
+                    "{} is gone, adding it to the list of deleted URLs",
+                    entry.url
+                );
+                result.push(entry);
+            }
+            Ok(status) => {
+                warn!(
+                    "{} was deleted but the server still returns HTTP status {} - skipping",
+                    entry.url, status
+                );
+            }
+            Err(e) => {
+                warn!("Can't check status of {}: {} - skipping", entry.url, e);
+            }
+        };
+    }
+    Ok(result)
+}
+
+/// Build list of URLs to submit from changed files (or URLs of a sitemap if root is None) and
+/// update the database for excluded files
+pub fn pending_from_changes(
+    cfg: &config::Configuration,
+    db: &mut rusqlite::Connection,
+    root: Option<&config::HtmlRoot>,
+    changes: scan::UpdateList,
+    dry_run: bool,
+) -> Result<Vec<PendingUrl>, Box<dyn Error>> {
+    let (pending, mut excluded) = match root {
+        Some(r) => build_pending_list(cfg, r, changes),
+        None => build_sitemap_pending_list(cfg, changes),
+    };
+    if !dry_run {
+        excluded.changed.append(&mut excluded.refreshed);
+        if let Err(e) = sqlite3::db_update(db, &excluded.changed, &excluded.deleted) {
+            bail!("Unable to update database: {}", e);
+        }
+    }
+    Ok(pending)
+}
+
+/// Scan all roots (or only the given files of the roots) and submit the changes together with
+/// the queue of pending submissions
+pub fn submit_roots(
+    cfg: &config::Configuration,
+    db: &mut rusqlite::Connection,
+    roots: &[config::HtmlRoot],
+    files: Option<&[String]>,
+    purge: bool,
+    rehash: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let extlist = match &cfg.file_extensions {
+        Some(v) => v,
+        None => bail!("List of file extensions is empty"),
+    };
+    let mut pending = Vec::<PendingUrl>::new();
+    let mut changed: usize = 0;
+
+    for root in roots {
+        let root_files: Option<Vec<String>> = files.map(|list| {
+            list.iter()
+                .filter(|f| config::root_for_file(roots, f).map(|r| &r.path) == Some(&root.path))
+                .cloned()
+                .collect()
+        });
+        if let Some(list) = &root_files {
+            if list.is_empty() {
+                continue;
+            }
+        }
+
+        let changes = match scan::build_update_list(
+            &root.path,
+            db,
+            cfg,
+            extlist,
+            purge,
+            rehash,
+            root_files.as_deref(),
+        ) {
+            Ok(v) => v,
+            Err(e) => bail!("Unable to build file list for {}: {}", root.path, e),
+        };
+        changed += changes.changed.len() + changes.deleted.len();
+        pending.append(&mut pending_from_changes(
+            cfg,
+            db,
+            Some(root),
+            changes,
+            dry_run,
+        )?);
+    }
+
+    // Even without changes, URLs from the queue of pending submissions must be processed
+    if changed == 0 {
+        info!("List of updated files is empty");
+    }
+    submit_pending(cfg, db, roots, pending, dry_run)
+}
+
+/// Submit a list of file names and URLs, e.g. read from stdin. Files are only stored in the
+/// database if update_hashes is set, missing files are submitted as removed URLs.
+pub fn submit_list(
+    cfg: &config::Configuration,
+    db: &mut rusqlite::Connection,
+    roots: &[config::HtmlRoot],
     list: Vec<String>,
+    update_hashes: bool,
     dry_run: bool,
 ) -> Result<(), Box<dyn Error>> {
-    // The list of URLs per submit is limited to 10000 - https://www.indexnow.org/documentation
-    let iter = list.len() / constants::BATCH_SIZE;
-    let remain = list.len() % constants::BATCH_SIZE;
-    debug!("List contains {} elements, {} iterations with fill batch size of {} + {} remaining elements", list.len(), iter, constants::BATCH_SIZE, remain);
-    for i in 0..iter {
-        // send full batch
-        if dry_run {
-            let dumped = serde_json::to_string_pretty(&build_dump_payload(
-                &cfg,
-                list[i * constants::BATCH_SIZE..(i + 1) * constants::BATCH_SIZE].to_vec(),
-            ))?;
-            info!(
-                "Would send data using HTTP POST to {}:\n{}",
-                cfg.submit, dumped
+    let mut urls = Vec::<String>::new();
+    let mut changed = Vec::<String>::new();
+    let mut deleted = Vec::<String>::new();
+
+    for entry in list {
+        if http::is_url(&entry) {
+            urls.push(entry);
+            continue;
+        }
+
+        let first = match roots.first() {
+            Some(v) => v,
+            None => {
+                warn!(
+                    "Can't map {} to URL without path to HTML data - skipping",
+                    entry
+                );
+                continue;
+            }
+        };
+        // File names can be relative to the (first) directory of the HTML data
+        let file = if entry.starts_with('/') || config::root_for_file(roots, &entry).is_some() {
+            entry
+        } else {
+            format!("{}{}", first.path, entry.trim_start_matches("./"))
+        };
+        if config::root_for_file(roots, &file).is_none() {
+            warn!(
+                "{} is not located below a directory of the HTML data - skipping",
+                file
             );
+            continue;
+        }
+
+        if let Some(extlist) = &cfg.file_extensions {
+            if !scan::match_extension_list(Path::new(&file), extlist) {
+                debug!(
+                    "Skipping {} because extension does not match the extension list {:?}",
+                    file, extlist
+                );
+                continue;
+            }
+        }
+
+        if Path::new(&file).exists() {
+            changed.push(file);
         } else {
-            info!("Submitting data to {}", cfg.submit);
-            let payload = build_post_payload(
-                &cfg,
-                list[i * constants::BATCH_SIZE..(i + 1) * constants::BATCH_SIZE].to_vec(),
-            )
-            .unwrap();
-            let mut http_client = http::build_client(constants::DEFAULT_TIMEOUT)?;
-            http::post(&mut http_client, &cfg.submit, payload)?;
+            deleted.push(file);
+        }
+    }
+    info!(
+        "Read {} URLs, {} changed files and {} removed files",
+        urls.len(),
+        changed.len(),
+        deleted.len()
+    );
+
+    let mut pending = Vec::<PendingUrl>::new();
+    for root in roots {
+        let in_root =
+            |f: &String| config::root_for_file(roots, f).map(|r| &r.path) == Some(&root.path);
+        let root_changed: Vec<String> = changed.iter().filter(|f| in_root(f)).cloned().collect();
+        let root_deleted: Vec<String> = deleted.iter().filter(|f| in_root(f)).cloned().collect();
+
+        if !update_hashes {
+            pending.append(&mut build_path_pending_list(
+                cfg,
+                root,
+                root_changed,
+                root_deleted,
+            ));
+            continue;
+        }
+
+        let mut hashed = Vec::<scan::Filehash>::new();
+        for f in root_changed {
+            match scan::file_hash(cfg, &f) {
+                Ok(v) => hashed.push(v),
+                Err(e) => warn!("Can't read {}: {} - skipping", f, e),
+            };
+        }
+        let list = scan::UpdateList {
+            changed: hashed,
+            deleted: root_deleted,
+            refreshed: Vec::new(),
+        };
+        pending.append(&mut pending_from_changes(
+            cfg,
+            db,
+            Some(root),
+            list,
+            dry_run,
+        )?);
+    }
+
+    // URLs are submitted as they are, but only for the host of the site
+    for u in urls {
+        if let Some(url) = validate_url(&u, "list") {
+            if !is_on_host(cfg, &url) {
+                warn!(
+                    "{} (from list) does not belong to the host {} - skipping",
+                    url, cfg.host
+                );
+                continue;
+            }
+            if is_excluded(&cfg.exclude_list, &url) || pending.iter().any(|p| p.url == url) {
+                continue;
+            }
+            pending.push(PendingUrl {
+                url,
+                file: None,
+                deleted: false,
+            });
+        }
+    }
+
+    submit_pending(cfg, db, roots, pending, dry_run)
+}
+
+/// Remove URLs disallowed by robots.txt, verify deleted URLs and submit the remaining URLs
+pub fn submit_pending(
+    cfg: &config::Configuration,
+    db: &mut rusqlite::Connection,
+    roots: &[config::HtmlRoot],
+    mut pending: Vec<PendingUrl>,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    // URLs queued by previous runs have already been checked, so robots.txt is only needed
+    // for new URLs
+    if let Some(robots_cfg) = cfg.robots_txt.as_ref().filter(|_| !pending.is_empty()) {
+        // robots.txt is read from the root of the site, without HTML data it is fetched
+        // from the site
+        let site_root = roots
+            .iter()
+            .find(|r| {
+                Url::parse(&r.base_url)
+                    .map(|u| u.path() == "/")
+                    .unwrap_or(false)
+            })
+            .or_else(|| roots.first());
+        let robots_location = match (&robots_cfg.path, site_root) {
+            (Some(v), _) => v.clone(),
+            (None, Some(root)) => format!("{}robots.txt", root.path),
+            (None, None) => match Url::parse(&cfg.base_url).and_then(|u| u.join("/robots.txt")) {
+                Ok(v) => v.to_string(),
+                Err(e) => bail!("Can't build URL of robots.txt: {}", e),
+            },
+        };
+        debug!("Reading robots.txt from {}", robots_location);
+        let robots = match robots::load(&robots_location, &robots_cfg.user_agent) {
+            Ok(v) => v,
+            Err(e) => bail!("Can't read robots.txt from {}: {}", robots_location, e),
+        };
+
+        // Disallowed URLs will never be submitted, so the database can be updated right away
+        let (allowed, disallowed) = remove_robots_disallowed(cfg, &robots, pending);
+        pending = allowed;
+        if !dry_run {
+            if let Err(e) = sqlite3::db_update_pending(db, &disallowed) {
+                bail!("Unable to update database: {}", e);
+            }
+        }
+    }
+
+    if cfg.verify_deleted {
+        pending = match verify_deleted(pending) {
+            Ok(v) => v,
+            Err(e) => bail!("Unable to verify deleted URLs: {}", e),
+        };
+    }
+
+    if let Err(e) = process_payload(cfg, db, pending, false, dry_run) {
+        bail!("Submission failed: {}", e);
+    }
+    Ok(())
+}
+
+/// Submit URLs of the site right away, e.g. given on the command line. URLs queued by previous
+/// runs are left for the next run.
+pub fn submit_urls(
+    cfg: &config::Configuration,
+    db: &mut rusqlite::Connection,
+    urls: &[String],
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut pending = Vec::<PendingUrl>::new();
+    for u in urls {
+        if !http::is_url(u) {
+            bail!("{} is not a HTTP or HTTPS URL", u);
+        }
+        let parsed = match Url::parse(u) {
+            Ok(v) => v,
+            Err(e) => bail!("{} is not a valid URL: {}", u, e),
+        };
+        if parsed.host_str() != Some(cfg.host.as_str()) {
+            bail!(
+                "{} does not belong to the host {} of site {}",
+                u,
+                cfg.host,
+                cfg.name
+            );
+        }
+
+        let url = parsed.to_string();
+        if pending.iter().any(|p| p.url == url) {
+            continue;
         }
+        pending.push(PendingUrl {
+            url,
+            file: None,
+            deleted: false,
+        });
     }
 
-    if dry_run {
-        let dumped = serde_json::to_string_pretty(&build_dump_payload(
-            &cfg,
-            list[iter * constants::BATCH_SIZE..].to_vec(),
-        ))?;
+    if let Err(e) = process_payload(cfg, db, pending, true, dry_run) {
+        bail!("Submission failed: {}", e);
+    }
+    Ok(())
+}
+
+/// Queue and submit new URLs. Unless ad_hoc is set, URLs queued by previous runs which are
+/// due are submitted too.
+fn process_payload(
+    cfg: &config::Configuration,
+    db: &mut rusqlite::Connection,
+    new: Vec<PendingUrl>,
+    ad_hoc: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut results = Vec::<SubmitResult>::new();
+    let now = chrono::Utc::now().timestamp();
+
+    // New changes are added to the queue of pending submissions first, so they will survive
+    // failed submissions. Entries already queued keep their retry schedule.
+    if !dry_run {
+        for endpoint in sqlite3::queue_prune_endpoints(db, &cfg.submit_list)? {
+            warn!(
+                "Removed queued URLs of {} which is no longer configured",
+                endpoint
+            );
+        }
+        sqlite3::queue_add(db, &cfg.submit_list, &new)?;
+    }
+
+    // Every endpoint has its own queue, so a failing endpoint will not affect the others
+    for endpoint in &cfg.submit_list {
+        let list = if ad_hoc {
+            new.clone()
+        } else if dry_run {
+            let mut queued: Vec<PendingUrl> = sqlite3::queue_get_due(db, endpoint, now)?
+                .into_iter()
+                .filter(|q| !new.iter().any(|n| n.url == q.url))
+                .collect();
+            queued.extend(new.iter().cloned());
+            queued
+        } else {
+            sqlite3::queue_get_due(db, endpoint, now)?
+        };
+
+        if list.is_empty() {
+            debug!("No URLs are due for submission to {}", endpoint);
+        }
+        results.push(submit_to_endpoint(
+            cfg, db, endpoint, &list, ad_hoc, dry_run, now,
+        )?);
+    }
+
+    let mut failed: usize = 0;
+    for r in &results {
         info!(
-            "Would send data using HTTP POST to {}:\n{}",
-            cfg.submit, dumped
+            "{}: {} URLs submitted, {} URLs failed",
+            r.endpoint, r.submitted, r.failed
+        );
+        failed += r.failed;
+    }
+
+    if !dry_run {
+        info!(
+            "{} URLs are still queued for submission",
+            sqlite3::queue_size(db)?
+        );
+
+        if cfg.history.retention > 0 {
+            let before = now - (cfg.history.retention * 86400) as i64;
+            let pruned = sqlite3::history_prune(db, before)?;
+            if pruned > 0 {
+                info!(
+                    "Removed {} entries older than {} days from submission history",
+                    pruned, cfg.history.retention
+                );
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!(
+            "{} URLs were not accepted and have been queued for the next run",
+            failed
         );
-    } else {
-        info!("Submitting data to {}", cfg.submit);
-        let payload =
-            build_post_payload(&cfg, list[iter * constants::BATCH_SIZE..].to_vec()).unwrap();
-        let mut http_client = http::build_client(constants::DEFAULT_TIMEOUT)?;
-        http::post(&mut http_client, &cfg.submit, payload)?;
     }
     Ok(())
 }
+
+fn submit_to_endpoint(
+    cfg: &config::Configuration,
+    db: &mut rusqlite::Connection,
+    endpoint: &str,
+    list: &[PendingUrl],
+    ad_hoc: bool,
+    dry_run: bool,
+    now: i64,
+) -> Result<SubmitResult, Box<dyn Error>> {
+    let mut result = SubmitResult {
+        endpoint: endpoint.to_string(),
+        submitted: 0,
+        failed: 0,
+    };
+
+    // The list of URLs per submit is limited to 10000 - https://www.indexnow.org/documentation
+    debug!(
+        "List for {} contains {} elements, submitting in batches of {} elements",
+        endpoint,
+        list.len(),
+        constants::BATCH_SIZE
+    );
+    // Rewrite rules can map URLs to other hosts, but every submission is limited to a single host
+    let mut hosts = Vec::<(String, Vec<PendingUrl>)>::new();
+    for entry in list {
+        let host = url_host(&entry.url).unwrap_or_else(|| cfg.host.clone());
+        match hosts.iter_mut().find(|(h, _)| *h == host) {
+            Some((_, v)) => v.push(entry.clone()),
+            None => hosts.push((host, vec![entry.clone()])),
+        };
+    }
+
+    let mut http_client = http::build_client(constants::DEFAULT_TIMEOUT)?;
+    for (host, host_list) in hosts {
+        for batch in host_list.chunks(constants::BATCH_SIZE) {
+            let urls: Vec<String> = batch.iter().map(|p| p.url.clone()).collect();
+
+            // A single ad hoc URL is submitted using the GET form of the IndexNow API
+            let get_url = if ad_hoc && list.len() == 1 {
+                Some(build_get_url(cfg, endpoint, &urls[0])?)
+            } else {
+                None
+            };
+
+            if dry_run {
+                match &get_url {
+                    Some(v) => info!("Would send HTTP GET request to {}", v),
+                    None => {
+                        let dumped =
+                            serde_json::to_string_pretty(&build_dump_payload(cfg, &host, urls))?;
+                        info!(
+                            "Would send data using HTTP POST to {}:\n{}",
+                            endpoint, dumped
+                        );
+                    }
+                };
+                continue;
+            }
+
+            let batch_id = format!("{:016x}", rand::random::<u64>());
+            info!(
+                "Submitting data for {} to {} (batch {})",
+                host, endpoint, batch_id
+            );
+            let mut reply = http::Reply::default();
+            let submitted = match &get_url {
+                Some(v) => http::submit_get(&mut http_client, v, &cfg.retry, &mut reply),
+                None => {
+                    let payload = build_post_payload(cfg, &host, urls)?;
+                    http::post(&mut http_client, endpoint, payload, &cfg.retry, &mut reply)
+                }
+            };
+            match submitted {
+                Ok(_) => {
+                    // Only store new hashes if the submission was accepted, otherwise the URLs
+                    // stay in the queue and will be submitted again on the next run
+                    sqlite3::queue_done(db, endpoint, batch)?;
+                    sqlite3::history_add(
+                        db,
+                        &batch_id,
+                        endpoint,
+                        batch,
+                        chrono::Utc::now().timestamp(),
+                        &reply,
+                        None,
+                    )?;
+                    result.submitted += batch.len();
+                }
+                Err(e) => {
+                    error!(
+                        "Submission of {} URLs to {} failed: {}",
+                        batch.len(),
+                        endpoint,
+                        e
+                    );
+                    sqlite3::queue_failed(db, endpoint, batch, &e.to_string(), now)?;
+                    sqlite3::history_add(
+                        db,
+                        &batch_id,
+                        endpoint,
+                        batch,
+                        chrono::Utc::now().timestamp(),
+                        &reply,
+                        Some(&e.to_string()),
+                    )?;
+                    result.failed += batch.len();
+                }
+            };
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE_URL: &str = "https://example.com/";
+    const HTML_DIR: &str = "/srv/www/";
+
+    fn mapping(trailing_slash: config::TrailingSlash) -> config::UrlMapping {
+        config::UrlMapping {
+            directory_index: vec!["index.html".to_string()],
+            strip_extensions: vec!["html".to_string()],
+            trailing_slash,
+        }
+    }
+
+    #[test]
+    fn massage_payload_encodes_file_names() {
+        let cases = [
+            ("/srv/www/index.html", "https://example.com/index.html"),
+            (
+                "/srv/www/blog/post.html",
+                "https://example.com/blog/post.html",
+            ),
+            ("/srv/www/a file.html", "https://example.com/a%20file.html"),
+            ("/srv/www/c#.html", "https://example.com/c%23.html"),
+            ("/srv/www/why?.html", "https://example.com/why%3F.html"),
+            ("/srv/www/100%.html", "https://example.com/100%25.html"),
+            (
+                "/srv/www/über/straße.html",
+                "https://example.com/%C3%BCber/stra%C3%9Fe.html",
+            ),
+        ];
+        for (file, url) in cases {
+            assert_eq!(
+                massage_payload(BASE_URL, HTML_DIR, file, &config::UrlMapping::default()).unwrap(),
+                url,
+                "{}",
+                file
+            );
+        }
+
+        // The path of the base URL is kept
+        assert_eq!(
+            massage_payload(
+                "https://example.com/site",
+                HTML_DIR,
+                "/srv/www/a b.html",
+                &config::UrlMapping::default()
+            )
+            .unwrap(),
+            "https://example.com/site/a%20b.html"
+        );
+        assert!(massage_payload(
+            BASE_URL,
+            HTML_DIR,
+            "/srv/other/index.html",
+            &config::UrlMapping::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn massage_payload_maps_clean_urls() {
+        use config::TrailingSlash::{Always, Keep, Never};
+
+        let cases = [
+            // Directory index
+            (Keep, "/srv/www/index.html", "https://example.com/"),
+            (Always, "/srv/www/index.html", "https://example.com/"),
+            (Never, "/srv/www/index.html", "https://example.com/"),
+            (
+                Keep,
+                "/srv/www/blog/index.html",
+                "https://example.com/blog/",
+            ),
+            (
+                Always,
+                "/srv/www/blog/index.html",
+                "https://example.com/blog/",
+            ),
+            (
+                Never,
+                "/srv/www/blog/index.html",
+                "https://example.com/blog",
+            ),
+            // Stripped extensions, case-insensitive
+            (Keep, "/srv/www/about.html", "https://example.com/about"),
+            (Keep, "/srv/www/about.HTML", "https://example.com/about"),
+            (Always, "/srv/www/about.html", "https://example.com/about/"),
+            (
+                Never,
+                "/srv/www/blog/post.html",
+                "https://example.com/blog/post",
+            ),
+            // Other files are not changed, even with a trailing slash mode
+            (Always, "/srv/www/doc.pdf", "https://example.com/doc.pdf"),
+            (Always, "/srv/www/.html", "https://example.com/.html"),
+            (
+                Never,
+                "/srv/www/blog/index.htm",
+                "https://example.com/blog/index.htm",
+            ),
+        ];
+        for (trailing_slash, file, url) in cases {
+            let m = mapping(trailing_slash);
+            assert_eq!(
+                massage_payload(BASE_URL, HTML_DIR, file, &m).unwrap(),
+                url,
+                "{} {:?}",
+                file,
+                m.trailing_slash
+            );
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use crate::constants;
+use crate::http;
+
+use log::{debug, info, warn};
+use regex::Regex;
+use std::error::Error;
+
+// Parser for robots.txt, see https://www.rfc-editor.org/rfc/rfc9309
+#[derive(Clone, Debug, Default)]
+pub struct Robots {
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+    regex: Regex,
+}
+
+#[derive(Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+}
+
+impl Robots {
+    /// URL path (including the query) is allowed if no rule matches or the longest matching
+    /// rule is an allow rule. On equal length, allow rules take precedence.
+    pub fn is_allowed(&self, path: &str) -> bool {
+        if path == "/robots.txt" {
+            return true;
+        }
+
+        let mut result: Option<&Rule> = None;
+        for rule in &self.rules {
+            if !rule.regex.is_match(path) {
+                continue;
+            }
+            result = match result {
+                Some(r)
+                    if r.pattern.len() > rule.pattern.len()
+                        || (r.pattern.len() == rule.pattern.len() && r.allow) =>
+                {
+                    Some(r)
+                }
+                _ => Some(rule),
+            };
+        }
+
+        match result {
+            Some(r) => {
+                debug!(
+                    "{} matches {} rule {}",
+                    path,
+                    if r.allow { "allow" } else { "disallow" },
+                    r.pattern
+                );
+                r.allow
+            }
+            None => true,
+        }
+    }
+}
+
+/// Load robots.txt from a file or URL. A missing robots.txt allows everything, see
+/// https://www.rfc-editor.org/rfc/rfc9309#section-2.3.1.3
+pub fn load(location: &str, user_agent: &str) -> Result<Robots, Box<dyn Error>> {
+    let mut http_client = http::build_client(constants::DEFAULT_TIMEOUT)?;
+    match http::read_location_optional(&mut http_client, location)? {
+        Some(raw) => Ok(parse(&String::from_utf8_lossy(&raw), user_agent)),
+        None => {
+            info!("{} does not exist, all URLs are allowed", location);
+            Ok(Robots::default())
+        }
+    }
+}
+
+pub fn parse(content: &str, user_agent: &str) -> Robots {
+    let mut groups = Vec::<Group>::new();
+    let mut current = Group::default();
+
+    for raw_line in content.lines() {
+        let line = match raw_line.find('#') {
+            Some(i) => &raw_line[..i],
+            None => raw_line,
+        }
+        .trim();
+        let (key, value) = match line.split_once(':') {
+            Some((k, v)) => (k.trim().to_lowercase(), v.trim()),
+            None => continue,
+        };
+
+        match key.as_str() {
+            "user-agent" => {
+                // A user-agent line after rules starts a new group
+                if !current.rules.is_empty() {
+                    groups.push(std::mem::take(&mut current));
+                }
+                current.agents.push(value.to_lowercase());
+            }
+            "allow" | "disallow" => {
+                if current.agents.is_empty() {
+                    warn!("Ignoring rule '{}' of robots.txt outside of a group", line);
+                    continue;
+                }
+                // Empty disallow rule allows everything
+                if value.is_empty() {
+                    continue;
+                }
+                match build_rule(key == "allow", value) {
+                    Some(v) => current.rules.push(v),
+                    None => warn!("Ignoring invalid rule '{}' of robots.txt", line),
+                };
+            }
+            _ => {}
+        };
+    }
+    if !current.agents.is_empty() {
+        groups.push(current);
+    }
+
+    // Rules of all groups for the user agent are combined, rules for * are only used if
+    // no group for the user agent exists
+    let ua = user_agent.to_lowercase();
+    let mut rules: Vec<Rule> = groups
+        .iter()
+        .filter(|g| g.agents.contains(&ua))
+        .flat_map(|g| g.rules.clone())
+        .collect();
+    if rules.is_empty() && !groups.iter().any(|g| g.agents.contains(&ua)) {
+        rules = groups
+            .iter()
+            .filter(|g| g.agents.iter().any(|a| a == "*"))
+            .flat_map(|g| g.rules.clone())
+            .collect();
+    }
+
+    debug!(
+        "Using {} rules of robots.txt for user agent {}",
+        rules.len(),
+        user_agent
+    );
+    Robots { rules }
+}
+
+fn build_rule(allow: bool, pattern: &str) -> Option<Rule> {
+    // * matches any sequence of characters, $ at the end of the pattern anchors the match
+    let (pattern_body, anchored) = match pattern.strip_suffix('$') {
+        Some(v) => (v, true),
+        None => (pattern, false),
+    };
+    let mut rxp = String::from("^");
+    rxp.push_str(
+        &pattern_body
+            .split('*')
+            .map(regex::escape)
+            .collect::<Vec<String>>()
+            .join(".*"),
+    );
+    if anchored {
+        rxp.push('$');
+    }
+
+    let regex = Regex::new(&rxp).ok()?;
+    Some(Rule {
+        allow,
+        pattern: pattern.to_string(),
+        regex,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "
+# Comment
+User-agent: *
+Disallow: /private/
+Allow: /private/public.html
+
+User-agent: bingbot
+User-agent: other
+Disallow: /drafts/   # trailing comment
+Allow: /drafts/published/
+Disallow: /*.pdf$
+Disallow: /search*q=
+
+User-agent: BingBot
+Disallow: /tmp
+
+User-agent: nobot
+Disallow:
+";
+
+    #[test]
+    fn parse_selects_groups() {
+        // Groups of the same user agent are merged, case-insensitive
+        assert_eq!(parse(ROBOTS, "bingbot").rules.len(), 5);
+        assert_eq!(parse(ROBOTS, "BINGBOT").rules.len(), 5);
+        // A user agent line shared by several agents
+        assert_eq!(parse(ROBOTS, "other").rules.len(), 4);
+        // Rules for * are only used without a group for the user agent
+        assert_eq!(parse(ROBOTS, "unknownbot").rules.len(), 2);
+        assert_eq!(parse(ROBOTS, "nobot").rules.len(), 0);
+        // Rules outside of a group and lines without key are ignored
+        assert_eq!(parse("Disallow: /\nfoo\n", "bingbot").rules.len(), 0);
+        assert_eq!(parse("", "bingbot").rules.len(), 0);
+    }
+
+    #[test]
+    fn is_allowed_matches_rules() {
+        let cases = [
+            ("unknownbot", "/index.html", true),
+            ("unknownbot", "/private/", false),
+            ("unknownbot", "/private/secret.html", false),
+            // The longest match wins
+            ("unknownbot", "/private/public.html", true),
+            ("bingbot", "/private/secret.html", true),
+            ("bingbot", "/drafts/post.html", false),
+            ("bingbot", "/drafts/published/post.html", true),
+            ("bingbot", "/tmp/file.html", false),
+            ("bingbot", "/tmpfile.html", false),
+            // $ anchors the end of the path, * matches any sequence of characters
+            ("bingbot", "/docs/manual.pdf", false),
+            ("bingbot", "/docs/manual.pdf.html", true),
+            ("bingbot", "/search?q=test", false),
+            ("bingbot", "/search/results?page=1&q=test", false),
+            ("bingbot", "/search", true),
+            // Empty disallow allows everything, robots.txt itself is always allowed
+            ("nobot", "/private/", true),
+            ("bingbot", "/robots.txt", true),
+        ];
+        for (agent, path, allowed) in cases {
+            assert_eq!(
+                parse(ROBOTS, agent).is_allowed(path),
+                allowed,
+                "{} {}",
+                agent,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn is_allowed_prefers_allow_on_equal_length() {
+        let robots = parse("User-agent: *\nDisallow: /page\nAllow: /page\n", "bot");
+        assert!(robots.is_allowed("/page.html"));
+        let robots = parse("User-agent: *\nAllow: /page\nDisallow: /page\n", "bot");
+        assert!(robots.is_allowed("/page.html"));
+        let robots = parse("User-agent: *\nDisallow: /\n", "bot");
+        assert!(!robots.is_allowed("/"));
+        assert!(!robots.is_allowed("/page.html"));
+    }
+}
@@ -1,17 +1,49 @@
+use crate::config;
+use crate::config::HashAlgorithm;
+use crate::constants;
+use crate::hash;
 use crate::sqlite3;
 
 use log::{debug, error, info, warn};
-use sha2::{Digest, Sha512};
 use std::collections::HashSet;
 use std::error::Error;
 use std::fmt;
 use std::fs;
+use std::io;
 use std::path::Path;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::UNIX_EPOCH;
 use walkdir::WalkDir;
 
+#[derive(Clone, Debug, Default)]
+pub struct UpdateList {
+    pub changed: Vec<Filehash>,
+    pub deleted: Vec<String>,
+    // Files with changed metadata but unchanged content
+    pub refreshed: Vec<Filehash>,
+}
+
+impl UpdateList {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.deleted.is_empty()
+    }
+}
+
+#[derive(Clone)]
 pub struct Filehash {
     pub file: String,
     pub hash: String,
+    pub algorithm: HashAlgorithm,
+    pub normalisation: Option<String>,
+    pub meta: Option<FileMeta>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileMeta {
+    pub size: i64,
+    pub mtime: i64,
+    pub inode: Option<i64>,
 }
 
 impl std::fmt::Debug for Filehash {
@@ -19,24 +51,258 @@ impl std::fmt::Debug for Filehash {
         f.debug_struct("Filehash")
             .field("file", &self.file)
             .field("hash", &self.hash)
+            .field("algorithm", &self.algorithm)
+            .field("normalisation", &self.normalisation)
+            .field("meta", &self.meta)
             .finish()
     }
 }
 
+impl FileMeta {
+    pub fn from_metadata(meta: &fs::Metadata) -> Self {
+        // Use nanosecond resolution, because a file can be changed several times within a second
+        let mtime = match meta.modified() {
+            Ok(v) => match v.duration_since(UNIX_EPOCH) {
+                Ok(d) => d.as_nanos() as i64,
+                Err(_) => 0,
+            },
+            Err(_) => 0,
+        };
+
+        #[cfg(unix)]
+        let inode = Some(std::os::unix::fs::MetadataExt::ino(meta) as i64);
+        #[cfg(not(unix))]
+        let inode = None;
+
+        FileMeta {
+            size: meta.len() as i64,
+            mtime,
+            inode,
+        }
+    }
+
+    fn matches(&self, other: &FileMeta, check_inode: bool) -> bool {
+        self.size == other.size
+            && self.mtime == other.mtime
+            && (!check_inode || self.inode == other.inode)
+    }
+}
+
+struct HashJob {
+    file: String,
+    meta: FileMeta,
+    normalise: bool,
+    from_db: Option<Filehash>,
+}
+
+struct HashResult {
+    job: HashJob,
+    hashes: Result<Vec<String>, io::Error>,
+}
+
 pub fn build_update_list(
     p: &str,
     db: &mut rusqlite::Connection,
-    extlist: Vec<String>,
+    cfg: &config::Configuration,
+    extlist: &[String],
     purge: bool,
-    dry_run: bool,
-) -> Result<Vec<String>, Box<dyn Error>> {
-    let mut result = Vec::<String>::new();
-    let mut inserts = Vec::<Filehash>::new();
+    rehash: bool,
+    files: Option<&[String]>,
+) -> Result<UpdateList, Box<dyn Error>> {
+    let algorithm = cfg.hash_algorithm;
+    let threads = cfg
+        .hash_threads
+        .unwrap_or_else(constants::default_hash_threads);
+    let mut result = Vec::<Filehash>::new();
+    let mut refreshed = Vec::<Filehash>::new();
+    let mut inserts: usize = 0;
     let mut deletes = Vec::<String>::new();
-    let mut updates = Vec::<Filehash>::new();
+    let mut updates: usize = 0;
     let mut seen_files = HashSet::<String>::new();
 
-    debug!("Scanning files in {}", p);
+    // Files are hashed by a pool of worker threads, database access is limited to this thread
+    let (job_tx, job_rx) = mpsc::channel::<HashJob>();
+    let (result_tx, result_rx) = mpsc::channel::<HashResult>();
+    let job_rx = Mutex::new(job_rx);
+
+    debug!("Scanning files in {} using {} hash threads", p, threads);
+    let hashed = thread::scope(|scope| -> Result<Vec<HashResult>, Box<dyn Error>> {
+        for _ in 0..threads.max(1) {
+            let result_tx = result_tx.clone();
+            let job_rx = &job_rx;
+            scope.spawn(move || loop {
+                // Lock is released before hashing starts
+                let job = match job_rx.lock() {
+                    Ok(rx) => rx.recv(),
+                    Err(_) => return,
+                };
+                let job = match job {
+                    Ok(v) => v,
+                    Err(_) => return,
+                };
+
+                // If the hash in the database was calculated using another algorithm, the hash
+                // using this algorithm is required to check if the content has changed
+                let mut algorithms = vec![algorithm];
+                if let Some(f) = &job.from_db {
+                    if f.algorithm != algorithm {
+                        algorithms.push(f.algorithm);
+                    }
+                }
+                let normalise = if job.normalise {
+                    cfg.normalise.as_ref()
+                } else {
+                    None
+                };
+                let hashes = hash::file_hashes(&job.file, &algorithms, normalise);
+                if result_tx.send(HashResult { job, hashes }).is_err() {
+                    return;
+                }
+            });
+        }
+        drop(result_tx);
+
+        // Only check the given files instead of all files below p, e.g. in watch mode
+        let walked = match files {
+            Some(list) => check_files(list, db, cfg, extlist, &job_tx, &mut deletes),
+            None => walk_files(p, db, cfg, extlist, rehash, &job_tx, &mut seen_files),
+        };
+        // Closing the job channel terminates the workers after all jobs are done
+        drop(job_tx);
+        walked?;
+
+        Ok(result_rx.iter().collect())
+    })?;
+
+    for hash_result in hashed {
+        let job = hash_result.job;
+        let fname = job.file;
+        let hashes = match hash_result.hashes {
+            Ok(v) => v,
+            Err(e) => {
+                // The file still exists, so it must not be purged as deleted
+                warn!("Can't read {}: {} - skipping", fname, e);
+                seen_files.insert(fname);
+                continue;
+            }
+        };
+        let hash_from_file = hashes[0].clone();
+
+        seen_files.insert(fname.clone());
+
+        debug!(
+            "Calculated {} hash of {} from file -> {}",
+            algorithm.as_str(),
+            fname,
+            hash_from_file
+        );
+
+        let fhash = Filehash {
+            file: fname.clone(),
+            hash: hash_from_file,
+            algorithm,
+            normalisation: normalisation_fingerprint(cfg, job.normalise),
+            meta: Some(job.meta),
+        };
+
+        // File not in database -> add it to the list of pending changes
+        let from_db = match job.from_db {
+            Some(v) => v,
+            None => {
+                debug!(
+                    "Hash for {} not found in database, adding it to list of pending changes",
+                    fname
+                );
+                inserts += 1;
+                result.push(fhash);
+                continue;
+            }
+        };
+
+        // Changed normalisation settings change the hash of every affected file, so the
+        // content can't be compared. Replace the stored hash instead of submitting all files.
+        if from_db.normalisation != fhash.normalisation {
+            debug!(
+                "Normalisation settings for {} have changed, replacing stored hash",
+                fname
+            );
+            refreshed.push(fhash);
+            continue;
+        }
+
+        // Compare using the algorithm of the database entry
+        let compare_hash = hashes.last().unwrap_or(&fhash.hash);
+
+        // File has changed, add it to the list of pending changes
+        if from_db.hash != *compare_hash {
+            debug!(
+                "File {} has changed DB:{} != FILE:{} - adding it to list of pending changes",
+                fname, from_db.hash, compare_hash
+            );
+            updates += 1;
+            result.push(fhash);
+            continue;
+        }
+
+        debug!(
+            "File {} has not changed DB:{} == FILE:{}",
+            fname, from_db.hash, compare_hash
+        );
+
+        // Content is unchanged, but metadata must be updated to skip hashing on the next run and
+        // hashes calculated by another algorithm are replaced
+        if from_db.algorithm != algorithm {
+            debug!(
+                "Replacing {} hash of {} by {} hash",
+                from_db.algorithm.as_str(),
+                fname,
+                algorithm.as_str()
+            );
+            refreshed.push(fhash);
+        } else if from_db.meta != fhash.meta {
+            refreshed.push(fhash);
+        }
+    }
+
+    // Hashing finishes in random order
+    result.sort_by(|a, b| a.file.cmp(&b.file));
+
+    if purge {
+        debug!("Purge option found, getting list of files from database");
+        let files_from_db = sqlite3::get_all_files(db)?;
+        // Only files below p, the database can contain files of other directories
+        let purge_list = files_from_db.difference(&seen_files);
+        for f in purge_list {
+            if f.starts_with(p) {
+                deletes.push(f.to_string());
+            }
+        }
+        deletes.sort();
+    }
+
+    // Database entries are only updated after a successful submission
+    info!(
+        "Pending changes: {} new files, {} changed files, {} deletions",
+        inserts,
+        updates,
+        deletes.len()
+    );
+    Ok(UpdateList {
+        changed: result,
+        deleted: deletes,
+        refreshed,
+    })
+}
+
+fn walk_files(
+    p: &str,
+    db: &rusqlite::Connection,
+    cfg: &config::Configuration,
+    extlist: &[String],
+    rehash: bool,
+    jobs: &mpsc::Sender<HashJob>,
+    seen_files: &mut HashSet<String>,
+) -> Result<(), Box<dyn Error>> {
     for entry in WalkDir::new(p) {
         let fs_obj = match entry {
             Ok(v) => v,
@@ -67,134 +333,194 @@ pub fn build_update_list(
             continue;
         }
 
-        // Matches extension?
-        debug!("Processing {}", fs_obj.path().display());
-        if !match_extension_list(fs_obj.path(), &extlist) {
-            debug!(
-                "Skipping {} because extension does not match the extension list {:?}",
-                fs_obj.path().display(),
-                extlist
-            );
-            continue;
-        }
+        add_file(
+            fs_obj.path(),
+            &meta,
+            db,
+            cfg,
+            extlist,
+            rehash,
+            jobs,
+            seen_files,
+        )?;
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn add_file(
+    path: &Path,
+    meta: &fs::Metadata,
+    db: &rusqlite::Connection,
+    cfg: &config::Configuration,
+    extlist: &[String],
+    rehash: bool,
+    jobs: &mpsc::Sender<HashJob>,
+    seen_files: &mut HashSet<String>,
+) -> Result<(), Box<dyn Error>> {
+    let algorithm = cfg.hash_algorithm;
+
+    // Matches extension?
+    debug!("Processing {}", path.display());
+    if !match_extension_list(path, extlist) {
         debug!(
-            "Extension of {} matches list of extensions {:?}",
-            fs_obj.path().display(),
+            "Skipping {} because extension does not match the extension list {:?}",
+            path.display(),
             extlist
         );
+        return Ok(());
+    }
+    debug!(
+        "Extension of {} matches list of extensions {:?}",
+        path.display(),
+        extlist
+    );
 
-        let fname = match fs_obj.path().to_str() {
-            Some(v) => v,
-            None => {
-                warn!("Invalid filename {} - skipping", fs_obj.path().display());
-                continue;
-            }
-        };
+    let fname = match path.to_str() {
+        Some(v) => v,
+        None => {
+            warn!("Invalid filename {} - skipping", path.display());
+            return Ok(());
+        }
+    };
 
-        // Check if file exists in the database
-        let sha512_from_db = match sqlite3::file_sha512_from_db(db, fname) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Unable to query database: {}", e);
-                return Err(e);
-            }
-        };
+    let file_meta = FileMeta::from_metadata(meta);
+    let normalise = match &cfg.normalise {
+        Some(n) => match_extension_list(path, &n.extensions),
+        None => false,
+    };
 
-        // File not in database -> Generate sha512 hash add to insertion list and list for IndexNow
-        if sha512_from_db.is_empty() {
-            debug!(
-                "SHA512 for {} not found in database, adding it to insertion list",
-                fname
-            );
-            let sha512_from_file = match file_sha512_from_file(fname) {
-                Ok(v) => v,
-                Err(e) => {
-                    warn!("Can't read {}: {} - skipping", fname, e);
-                    continue;
-                }
-            };
+    // Check if file exists in the database
+    let from_db = match sqlite3::file_hash_from_db(db, fname) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Unable to query database: {}", e);
+            return Err(e);
+        }
+    };
 
-            debug!(
-                "Calculated SHA512 hash of {} from file -> {}",
-                fname, sha512_from_file
-            );
-            let fhash = Filehash {
-                file: fname.to_string(),
-                hash: sha512_from_file,
-            };
-            inserts.push(fhash);
+    // Size and modification time (and inode) are unchanged -> skip hashing, unless the
+    // hash must be replaced by a hash using the configured algorithm
+    if !rehash {
+        if let Some(Filehash {
+            algorithm: db_algorithm,
+            normalisation: db_normalisation,
+            meta: Some(m),
+            ..
+        }) = &from_db
+        {
+            if *db_algorithm == algorithm
+                && *db_normalisation == normalisation_fingerprint(cfg, normalise)
+                && m.matches(&file_meta, cfg.check_inode)
+            {
+                debug!(
+                    "Metadata of {} has not changed, skipping calculation of hash",
+                    fname
+                );
+                seen_files.insert(fname.to_string());
+                return Ok(());
+            }
+        }
+    }
 
-            seen_files.insert(fname.to_string());
+    jobs.send(HashJob {
+        file: fname.to_string(),
+        meta: file_meta,
+        normalise,
+        from_db,
+    })?;
+    Ok(())
+}
 
-            result.push(fname.to_string());
-            continue;
-        }
+/// Check list of files, e.g. reported by inotify. Missing files (or directories) which are
+/// found in the database are added to the list of deleted files.
+fn check_files(
+    files: &[String],
+    db: &rusqlite::Connection,
+    cfg: &config::Configuration,
+    extlist: &[String],
+    jobs: &mpsc::Sender<HashJob>,
+    deletes: &mut Vec<String>,
+) -> Result<(), Box<dyn Error>> {
+    let mut seen_files = HashSet::<String>::new();
+    let mut db_files: Option<HashSet<String>> = None;
 
-        // File exists in database -> calculate it's current SHA512
-        let sha512_from_file = match file_sha512_from_file(fname) {
+    for f in files {
+        let meta = match fs::metadata(f) {
             Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // A removed directory removes all files below it
+                let all = match db_files.take() {
+                    Some(v) => v,
+                    None => sqlite3::get_all_files(db)?,
+                };
+                let prefix = format!("{}/", f);
+                for known in &all {
+                    if (known == f || known.starts_with(&prefix)) && !deletes.contains(known) {
+                        debug!(
+                            "{} has been removed, adding it to list of deleted files",
+                            known
+                        );
+                        deletes.push(known.to_string());
+                    }
+                }
+                db_files = Some(all);
+                continue;
+            }
             Err(e) => {
-                warn!("Can't read {}: {} - skipping", fname, e);
+                warn!("Unable to read metadata for {}: {} - skipping", f, e);
                 continue;
             }
         };
 
-        seen_files.insert(fname.to_string());
-
-        debug!(
-            "Calculated SHA512 hash of {} from file -> {}",
-            fname, sha512_from_file
-        );
-        // File has changed, add it to update list for the database and IndexNow
-        if sha512_from_db != sha512_from_file {
-            debug!(
-                "File {} has changed DB:{} != FILE:{} - adding it to update list",
-                fname, sha512_from_db, sha512_from_file
-            );
-            let fhash = Filehash {
-                file: fname.to_string(),
-                hash: sha512_from_file,
-            };
-            updates.push(fhash);
-            result.push(fname.to_string());
+        if !meta.is_file() {
+            debug!("{} is not a file - skipping", f);
             continue;
         }
-
-        debug!(
-            "File {} has not changed DB:{} == FILE:{}",
-            fname, sha512_from_db, sha512_from_file
-        );
+        // Files were reported as changed, so the hash is always calculated
+        add_file(
+            Path::new(f),
+            &meta,
+            db,
+            cfg,
+            extlist,
+            true,
+            jobs,
+            &mut seen_files,
+        )?;
     }
+    deletes.sort();
+    Ok(())
+}
 
-    if purge {
-        debug!("Purge option found, getting list of files from database");
-        let files_from_db = sqlite3::get_all_files(db)?;
-        let purge_list = files_from_db.difference(&seen_files);
-        for p in purge_list {
-            deletes.push(p.to_string());
-        }
-    }
+/// Calculate hash of a single file
+pub fn file_hash(cfg: &config::Configuration, f: &str) -> Result<Filehash, io::Error> {
+    let meta = fs::metadata(f)?;
+    let normalise = match &cfg.normalise {
+        Some(n) => match_extension_list(Path::new(f), &n.extensions),
+        None => false,
+    };
+    let norm = if normalise {
+        cfg.normalise.as_ref()
+    } else {
+        None
+    };
+    let hashes = hash::file_hashes(f, &[cfg.hash_algorithm], norm)?;
 
-    info!(
-        "Updating database: {} inserts, {} updates, {} deletions",
-        inserts.len(),
-        updates.len(),
-        deletes.len()
-    );
-    if !dry_run {
-        sqlite3::db_update(db, inserts, updates, deletes)?;
-    }
-    Ok(result)
+    Ok(Filehash {
+        file: f.to_string(),
+        hash: hashes[0].clone(),
+        algorithm: cfg.hash_algorithm,
+        normalisation: normalisation_fingerprint(cfg, normalise),
+        meta: Some(FileMeta::from_metadata(&meta)),
+    })
 }
 
-fn file_sha512_from_file(f: &str) -> Result<String, Box<dyn Error>> {
-    let _raw = fs::read(f)?;
-    let raw: &[u8] = &_raw;
-    let mut sha512sum = Sha512::new();
-    sha512sum.update(raw);
-    let hash = sha512sum.finalize();
-
-    Ok(hex::encode(hash))
+fn normalisation_fingerprint(cfg: &config::Configuration, normalise: bool) -> Option<String> {
+    if !normalise {
+        return None;
+    }
+    cfg.normalise.as_ref().map(|n| n.fingerprint.clone())
 }
 
 pub fn match_extension_list(f: &std::path::Path, e: &[String]) -> bool {
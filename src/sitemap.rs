@@ -0,0 +1,334 @@
+use crate::config;
+use crate::config::ChangeDetection;
+use crate::constants;
+use crate::hash;
+use crate::html;
+use crate::http;
+use crate::payload;
+use crate::scan;
+use crate::sqlite3;
+
+use log::{debug, info, warn};
+use regex::Regex;
+use simple_error::bail;
+use std::collections::HashSet;
+use std::error::Error;
+
+#[derive(Clone, Debug)]
+struct SitemapEntry {
+    loc: String,
+    lastmod: Option<String>,
+}
+
+/// Build list of changed and deleted URLs from a sitemap or sitemap index. URLs are stored in the
+/// database like files, using the URL as file name.
+pub fn build_update_list(
+    db: &mut rusqlite::Connection,
+    cfg: &config::Configuration,
+    sitemap_cfg: &config::SitemapConfiguration,
+    purge: bool,
+) -> Result<scan::UpdateList, Box<dyn Error>> {
+    let algorithm = cfg.hash_algorithm;
+    let mut http_client = http::build_client(constants::DEFAULT_TIMEOUT)?;
+    let mut result = Vec::<scan::Filehash>::new();
+    let mut refreshed = Vec::<scan::Filehash>::new();
+    let mut inserts: usize = 0;
+    let mut updates: usize = 0;
+    let mut deletes = Vec::<String>::new();
+    let mut seen_urls = HashSet::<String>::new();
+    let mut visited = HashSet::<String>::new();
+
+    let entries = read_sitemap(&mut http_client, &sitemap_cfg.location, 0, &mut visited)?;
+    info!(
+        "Found {} URLs in sitemap {}",
+        entries.len(),
+        sitemap_cfg.location
+    );
+
+    for entry in entries {
+        if !seen_urls.insert(entry.loc.clone()) {
+            debug!("{} is listed more than once in the sitemap", entry.loc);
+            continue;
+        }
+        // Submissions are limited to the host of the key
+        if !payload::is_on_host(cfg, &entry.loc) {
+            warn!(
+                "{} (from sitemap) does not belong to the host {} - skipping",
+                entry.loc, cfg.host
+            );
+            continue;
+        }
+
+        let from_db = sqlite3::file_hash_from_db(db, &entry.loc)?;
+
+        let lastmod = match sitemap_cfg.change_detection {
+            ChangeDetection::Lastmod => entry.lastmod.clone(),
+            ChangeDetection::Hash => None,
+        };
+
+        // Use <lastmod> if available, fetch and hash the page otherwise
+        let (fhash, compare_hash) = match lastmod {
+            Some(v) => (
+                scan::Filehash {
+                    file: entry.loc.clone(),
+                    hash: v.clone(),
+                    algorithm,
+                    normalisation: Some(constants::SITEMAP_LASTMOD_MARKER.to_string()),
+                    meta: None,
+                },
+                v,
+            ),
+            None => {
+                let content = match http::get(&mut http_client, &entry.loc) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Can't fetch {}: {} - skipping", entry.loc, e);
+                        continue;
+                    }
+                };
+
+                let mut algorithms = vec![algorithm];
+                if let Some(f) = &from_db {
+                    if f.algorithm != algorithm {
+                        algorithms.push(f.algorithm);
+                    }
+                }
+                let hashes = hash::content_hashes(&content, &algorithms, cfg.normalise.as_ref());
+                debug!(
+                    "Calculated {} hash of {} from page -> {}",
+                    algorithm.as_str(),
+                    entry.loc,
+                    hashes[0]
+                );
+                (
+                    scan::Filehash {
+                        file: entry.loc.clone(),
+                        hash: hashes[0].clone(),
+                        algorithm,
+                        normalisation: cfg.normalise.as_ref().map(|n| n.fingerprint.clone()),
+                        meta: None,
+                    },
+                    hashes.last().unwrap_or(&hashes[0]).clone(),
+                )
+            }
+        };
+
+        let from_db = match from_db {
+            Some(v) => v,
+            None => {
+                debug!(
+                    "{} not found in database, adding it to list of pending changes",
+                    entry.loc
+                );
+                inserts += 1;
+                result.push(fhash);
+                continue;
+            }
+        };
+
+        // Switching between <lastmod> and page hash (or changed normalisation settings) can't be
+        // compared, replace the stored value instead of submitting the URL
+        if from_db.normalisation != fhash.normalisation {
+            debug!(
+                "Change detection for {} has changed, replacing stored value",
+                entry.loc
+            );
+            refreshed.push(fhash);
+            continue;
+        }
+
+        if from_db.hash != compare_hash {
+            debug!(
+                "{} has changed DB:{} != SITEMAP:{} - adding it to list of pending changes",
+                entry.loc, from_db.hash, compare_hash
+            );
+            updates += 1;
+            result.push(fhash);
+            continue;
+        }
+
+        debug!("{} has not changed", entry.loc);
+        if from_db.algorithm != algorithm {
+            refreshed.push(fhash);
+        }
+    }
+
+    if purge {
+        debug!("Purge option found, getting list of URLs from database");
+        for u in sqlite3::get_all_files(db)? {
+            if http::is_url(&u) && !seen_urls.contains(&u) {
+                deletes.push(u);
+            }
+        }
+        deletes.sort();
+    }
+
+    info!(
+        "Pending changes: {} new URLs, {} changed URLs, {} deletions",
+        inserts,
+        updates,
+        deletes.len()
+    );
+    Ok(scan::UpdateList {
+        changed: result,
+        deleted: deletes,
+        refreshed,
+    })
+}
+
+fn read_sitemap(
+    http_client: &mut reqwest::blocking::Client,
+    location: &str,
+    depth: usize,
+    visited: &mut HashSet<String>,
+) -> Result<Vec<SitemapEntry>, Box<dyn Error>> {
+    if depth > constants::SITEMAP_MAX_DEPTH {
+        bail!(
+            "sitemap {} exceeds the maximal nesting of sitemap indexes",
+            location
+        );
+    }
+    if !visited.insert(location.to_string()) {
+        warn!("Sitemap {} has already been read - skipping", location);
+        return Ok(Vec::new());
+    }
+
+    let raw = http::read_location(http_client, location)?;
+    let content = String::from_utf8_lossy(&raw);
+    let (urls, sitemaps) = parse(&content)?;
+    debug!(
+        "Sitemap {} contains {} URLs and {} sitemaps",
+        location,
+        urls.len(),
+        sitemaps.len()
+    );
+
+    let mut result = urls;
+    for s in sitemaps {
+        result.append(&mut read_sitemap(http_client, &s.loc, depth + 1, visited)?);
+    }
+    Ok(result)
+}
+
+/// Parse sitemap, returns list of URLs and list of sitemaps (for sitemap indexes)
+fn parse(content: &str) -> Result<(Vec<SitemapEntry>, Vec<SitemapEntry>), Box<dyn Error>> {
+    let entry_re = Regex::new(constants::SITEMAP_ENTRY_REGEX)?;
+    let loc_re = Regex::new(constants::SITEMAP_LOC_REGEX)?;
+    let lastmod_re = Regex::new(constants::SITEMAP_LASTMOD_REGEX)?;
+    let mut urls = Vec::<SitemapEntry>::new();
+    let mut sitemaps = Vec::<SitemapEntry>::new();
+
+    for cap in entry_re.captures_iter(content) {
+        let loc = match loc_re.captures(&cap[2]) {
+            Some(v) => xml_text(&v[1]),
+            None => {
+                warn!("Ignoring sitemap entry without <loc>");
+                continue;
+            }
+        };
+        let lastmod = lastmod_re
+            .captures(&cap[2])
+            .map(|v| xml_text(&v[1]))
+            .filter(|v| !v.is_empty());
+
+        let entry = SitemapEntry { loc, lastmod };
+        if cap[1].eq_ignore_ascii_case("sitemap") {
+            sitemaps.push(entry);
+        } else {
+            urls.push(entry);
+        }
+    }
+    Ok((urls, sitemaps))
+}
+
+fn xml_text(raw: &str) -> String {
+    let trimmed = raw.trim();
+    match trimmed
+        .strip_prefix("<![CDATA[")
+        .and_then(|v| v.strip_suffix("]]>"))
+    {
+        Some(v) => v.trim().to_string(),
+        // &apos; is predefined in XML, but not handled for HTML
+        None => html::html_unescape(&trimmed.replace("&apos;", "'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_urlset() {
+        let content = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url>
+    <loc>https://example.com/</loc>
+    <lastmod>2024-01-02</lastmod>
+  </url>
+  <url><loc>https://example.com/a?x=1&amp;y=2</loc><lastmod></lastmod></url>
+  <URL>
+    <LOC><![CDATA[ https://example.com/b?x=1&y=2 ]]></LOC>
+    <lastmod> </lastmod>
+  </URL>
+  <url><lastmod>2024-01-03</lastmod></url>
+  <url xml:lang="en"><loc>https://example.com/&lt;c&gt;&quot;&apos;&#39;</loc></url>
+</urlset>"#;
+        let (urls, sitemaps) = parse(content).unwrap();
+        assert!(sitemaps.is_empty());
+
+        // Entries without <loc> are ignored, empty <lastmod> is missing
+        let expected = [
+            ("https://example.com/", Some("2024-01-02")),
+            ("https://example.com/a?x=1&y=2", None),
+            ("https://example.com/b?x=1&y=2", None),
+            ("https://example.com/<c>\"''", None),
+        ];
+        assert_eq!(urls.len(), expected.len());
+        for (entry, (loc, lastmod)) in urls.iter().zip(expected) {
+            assert_eq!(entry.loc, loc);
+            assert_eq!(entry.lastmod.as_deref(), lastmod, "{}", loc);
+        }
+    }
+
+    #[test]
+    fn parse_sitemapindex() {
+        let content = r#"<?xml version="1.0" encoding="UTF-8"?>
+<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap>
+    <loc>https://example.com/sitemap-1.xml</loc>
+    <lastmod>2024-01-02T10:00:00+00:00</lastmod>
+  </sitemap>
+  <sitemap><loc>https://example.com/sitemap-2.xml</loc></sitemap>
+  <sitemap></sitemap>
+</sitemapindex>"#;
+        let (urls, sitemaps) = parse(content).unwrap();
+        assert!(urls.is_empty());
+        assert_eq!(sitemaps.len(), 2);
+        assert_eq!(sitemaps[0].loc, "https://example.com/sitemap-1.xml");
+        assert_eq!(
+            sitemaps[0].lastmod.as_deref(),
+            Some("2024-01-02T10:00:00+00:00")
+        );
+        assert_eq!(sitemaps[1].loc, "https://example.com/sitemap-2.xml");
+        assert_eq!(sitemaps[1].lastmod, None);
+
+        let (urls, sitemaps) = parse("").unwrap();
+        assert!(urls.is_empty() && sitemaps.is_empty());
+    }
+
+    #[test]
+    fn xml_text_unescapes() {
+        let cases = [
+            ("  plain  ", "plain"),
+            ("a &amp; b", "a & b"),
+            ("&amp;lt;", "&lt;"),
+            ("&lt;&gt;&quot;&apos;&#39;", "<>\"''"),
+            ("<![CDATA[a &amp; b]]>", "a &amp; b"),
+            (" <![CDATA[ a & b ]]> ", "a & b"),
+            ("", ""),
+        ];
+        for (raw, text) in cases {
+            assert_eq!(xml_text(raw), text, "{}", raw);
+        }
+    }
+}
@@ -1,27 +1,198 @@
+use crate::config;
 use crate::constants;
+use crate::http;
+use crate::payload;
 use crate::scan;
-use log::info;
+use log::{debug, info};
+use rusqlite::types::ValueRef;
 use rusqlite::Result;
+use simple_error::bail;
 use std::collections::HashSet;
 use std::error::Error;
 use std::fs;
 
+// Migrations of the database schema, the schema version stored as user_version of the database
+// is the number of applied migrations. New migrations must be appended.
+type Migration = (
+    &'static str,
+    fn(&rusqlite::Transaction) -> Result<(), Box<dyn Error>>,
+);
+const MIGRATIONS: [Migration; 2] = [
+    (
+        "create tables and add columns missing in databases without schema version",
+        migrate_unversioned,
+    ),
+    ("add table of submission history", migrate_history),
+];
+
+/// Open database, the database is created if it doesn't exist and pending migrations are applied
 pub fn open(f: &str) -> Result<rusqlite::Connection, Box<dyn Error>> {
     let exists = fs::metadata(f).is_ok();
 
-    let db = match rusqlite::Connection::open(f) {
+    let mut db = match rusqlite::Connection::open(f) {
         Ok(v) => v,
         Err(e) => return Err(Box::new(e)),
     };
 
     if !exists {
         info!("Database file {} does not exist, creating it", f);
-        db.execute(constants::SQLITE3_SCHEMA, [])?;
     }
 
+    migrate(&mut db, f, false)?;
+    Ok(db)
+}
+
+/// Open existing database, pending migrations are applied
+pub fn open_existing(f: &str) -> Result<rusqlite::Connection, Box<dyn Error>> {
+    let mut db = connect_existing(f)?;
+    migrate(&mut db, f, false)?;
+    Ok(db)
+}
+
+/// Apply pending migrations to an existing database, only list them if dry_run is set
+pub fn migrate_file(f: &str, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    let mut db = connect_existing(f)?;
+    migrate(&mut db, f, dry_run)
+}
+
+/// Open existing database read-only, pending migrations are not applied
+pub fn open_read_only(f: &str) -> Result<rusqlite::Connection, Box<dyn Error>> {
+    if fs::metadata(f).is_err() {
+        bail!("Database file {} does not exist", f);
+    }
+
+    let db = rusqlite::Connection::open_with_flags(
+        f,
+        rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY
+            | rusqlite::OpenFlags::SQLITE_OPEN_URI
+            | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
     Ok(db)
 }
 
+fn connect_existing(f: &str) -> Result<rusqlite::Connection, Box<dyn Error>> {
+    if fs::metadata(f).is_err() {
+        bail!("Database file {} does not exist", f);
+    }
+
+    let db = rusqlite::Connection::open_with_flags(
+        f,
+        rusqlite::OpenFlags::default() & !rusqlite::OpenFlags::SQLITE_OPEN_CREATE,
+    )?;
+    Ok(db)
+}
+
+pub fn schema_version(db: &rusqlite::Connection) -> Result<usize, Box<dyn Error>> {
+    let version: usize = db.query_row("PRAGMA user_version;", [], |row| row.get(0))?;
+    Ok(version)
+}
+
+/// Schema version of databases with all migrations applied
+pub fn latest_schema_version() -> usize {
+    MIGRATIONS.len()
+}
+
+fn migrate(db: &mut rusqlite::Connection, f: &str, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    let version = schema_version(db)?;
+    let latest = MIGRATIONS.len();
+
+    if version > latest {
+        bail!(
+            "Database {} has schema version {}, but this version of {} only supports schema versions up to {}",
+            f,
+            version,
+            constants::NAME,
+            latest
+        );
+    }
+    if version == latest {
+        debug!("Schema version {} of database {} is up to date", version, f);
+        return Ok(());
+    }
+
+    for (i, (description, apply)) in MIGRATIONS.iter().enumerate().skip(version) {
+        let new_version = i + 1;
+        if dry_run {
+            info!(
+                "Would migrate database {} to schema version {}: {}",
+                f, new_version, description
+            );
+            continue;
+        }
+
+        info!(
+            "Migrating database {} to schema version {}: {}",
+            f, new_version, description
+        );
+        // Every migration is applied completely or not at all
+        let tx = db.transaction()?;
+        if let Err(e) = apply(&tx) {
+            bail!("Migration to schema version {} failed: {}", new_version, e);
+        }
+        tx.execute_batch(&format!("PRAGMA user_version = {};", new_version))?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+// Databases created before schema versions were introduced can be at any state of the schema
+fn migrate_unversioned(tx: &rusqlite::Transaction) -> Result<(), Box<dyn Error>> {
+    tx.execute(constants::SQLITE3_SCHEMA, [])?;
+    migrate_table(tx, "files", &constants::SQLITE3_FILES_COLUMNS)?;
+    tx.execute(constants::SQLITE3_QUEUE_SCHEMA, [])?;
+    migrate_table(tx, "queue", &constants::SQLITE3_QUEUE_COLUMNS)?;
+    tx.execute(constants::SQLITE3_STATE_SCHEMA, [])?;
+    Ok(())
+}
+
+fn migrate_history(tx: &rusqlite::Transaction) -> Result<(), Box<dyn Error>> {
+    tx.execute_batch(constants::SQLITE3_HISTORY_SCHEMA)?;
+    Ok(())
+}
+
+fn migrate_table(
+    db: &rusqlite::Connection,
+    table: &str,
+    added: &[(&str, &str)],
+) -> Result<(), Box<dyn Error>> {
+    let mut columns = HashSet::<String>::new();
+    let mut statement = db.prepare(&format!("PRAGMA table_info({});", table))?;
+    let mut result_iter = statement.query([])?;
+
+    while let Some(row) = result_iter.next()? {
+        columns.insert(row.get(1)?);
+    }
+
+    for (old, new) in constants::SQLITE3_RENAMED_COLUMNS {
+        if columns.contains(old) && !columns.contains(new) {
+            info!("Renaming column {} of table {} to {}", old, table, new);
+            db.execute(
+                &format!("ALTER TABLE {} RENAME COLUMN {} TO {};", table, old, new),
+                [],
+            )?;
+        }
+    }
+
+    for (name, column_type) in added {
+        if !columns.contains(*name) {
+            info!("Adding column {} to table {}", name, table);
+            db.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {};", table, name, column_type),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// Rows without algorithm were created before the hash algorithm could be selected
+fn algorithm_from_db(algorithm: Option<String>) -> config::HashAlgorithm {
+    algorithm
+        .as_deref()
+        .and_then(config::HashAlgorithm::from_str)
+        .unwrap_or(config::HashAlgorithm::Sha512)
+}
+
 pub fn get_all_files(db: &rusqlite::Connection) -> Result<HashSet<String>, Box<dyn Error>> {
     let mut result = HashSet::<String>::new();
     let mut statement = db.prepare("SELECT filename FROM files;")?;
@@ -34,49 +205,652 @@ pub fn get_all_files(db: &rusqlite::Connection) -> Result<HashSet<String>, Box<d
     Ok(result)
 }
 
-pub fn file_sha512_from_db(db: &rusqlite::Connection, f: &str) -> Result<String, Box<dyn Error>> {
-    let count: u64 = db.query_row(
-        "SELECT COUNT(sha512) FROM files WHERE filename=:fname;",
-        &[(":fname", f)],
-        |row| row.get(0),
+pub fn file_count(db: &rusqlite::Connection) -> Result<u64, Box<dyn Error>> {
+    let count: u64 = db.query_row("SELECT COUNT(filename) FROM files;", [], |row| row.get(0))?;
+    Ok(count)
+}
+
+/// Result of the integrity check of the database, a single "ok" if no problems were found
+pub fn integrity_check(db: &rusqlite::Connection) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut result = Vec::<String>::new();
+    let mut statement = db.prepare("PRAGMA integrity_check;")?;
+    let mut result_iter = statement.query([])?;
+
+    while let Some(row) = result_iter.next()? {
+        result.push(row.get(0)?);
+    }
+
+    Ok(result)
+}
+
+pub fn file_hash_from_db(
+    db: &rusqlite::Connection,
+    f: &str,
+) -> Result<Option<scan::Filehash>, Box<dyn Error>> {
+    let mut statement =
+        db.prepare("SELECT hash, algorithm, size, mtime, inode, normalisation FROM files WHERE filename=:fname;")?;
+    let mut result_iter = statement.query(&[(":fname", f)])?;
+
+    let row = match result_iter.next()? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    let size: Option<i64> = row.get(2)?;
+    let mtime: Option<i64> = row.get(3)?;
+    let meta = match (size, mtime) {
+        (Some(size), Some(mtime)) => Some(scan::FileMeta {
+            size,
+            mtime,
+            inode: row.get(4)?,
+        }),
+        _ => None,
+    };
+    Ok(Some(scan::Filehash {
+        file: f.to_string(),
+        hash: row.get(0)?,
+        algorithm: algorithm_from_db(row.get(1)?),
+        normalisation: row.get(5)?,
+        meta,
+    }))
+}
+
+pub fn db_update(
+    db: &mut rusqlite::Connection,
+    upd: &[scan::Filehash],
+    del: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let tx = db.transaction()?;
+
+    for u in upd {
+        let meta = u.meta.as_ref();
+        tx.execute(
+            "INSERT OR REPLACE INTO files (filename, hash, algorithm, normalisation, size, mtime, inode) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+            rusqlite::params![
+                u.file,
+                u.hash,
+                u.algorithm.as_str(),
+                u.normalisation,
+                meta.map(|m| m.size),
+                meta.map(|m| m.mtime),
+                meta.and_then(|m| m.inode)
+            ],
+        )?;
+    }
+
+    for d in del {
+        tx.execute("DELETE FROM files WHERE filename=?1", [d])?;
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+/// Update database for pending URLs without submitting them
+pub fn db_update_pending(
+    db: &mut rusqlite::Connection,
+    list: &[payload::PendingUrl],
+) -> Result<(), Box<dyn Error>> {
+    let mut upd = Vec::<scan::Filehash>::new();
+    let mut del = Vec::<String>::new();
+
+    for p in list {
+        if let Some(f) = &p.file {
+            if p.deleted {
+                del.push(f.file.clone());
+            } else {
+                upd.push(f.clone());
+            }
+        }
+    }
+    db_update(db, &upd, &del)
+}
+
+pub fn queue_add(
+    db: &mut rusqlite::Connection,
+    endpoints: &[String],
+    list: &[payload::PendingUrl],
+) -> Result<(), Box<dyn Error>> {
+    let tx = db.transaction()?;
+
+    for p in list {
+        let filename = p.file.as_ref().map(|f| &f.file);
+        let hash = p.file.as_ref().map(|f| &f.hash);
+        let algorithm = p.file.as_ref().map(|f| f.algorithm.as_str());
+        let normalisation = p.file.as_ref().and_then(|f| f.normalisation.as_ref());
+
+        // The same content is already queued, don't queue it again for endpoints which
+        // already accepted it
+        let queued: u64 = tx.query_row(
+            "SELECT COUNT(url) FROM queue WHERE url=?1 AND hash IS ?2 AND deleted=?3;",
+            rusqlite::params![p.url, hash, p.deleted],
+            |row| row.get(0),
+        )?;
+        if queued > 0 {
+            continue;
+        }
+
+        // Keep number of attempts and time of next attempt for URLs already in the queue
+        for endpoint in endpoints {
+            tx.execute(
+                "INSERT INTO queue (url, endpoint, filename, hash, algorithm, normalisation, deleted) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) ON CONFLICT(url, endpoint) DO UPDATE SET filename=excluded.filename, hash=excluded.hash, algorithm=excluded.algorithm, normalisation=excluded.normalisation, deleted=excluded.deleted;",
+                rusqlite::params![
+                    p.url,
+                    endpoint,
+                    filename,
+                    hash,
+                    algorithm,
+                    normalisation,
+                    p.deleted
+                ],
+            )?;
+        }
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn queue_get_due(
+    db: &rusqlite::Connection,
+    endpoint: &str,
+    now: i64,
+) -> Result<Vec<payload::PendingUrl>, Box<dyn Error>> {
+    let mut result = Vec::<payload::PendingUrl>::new();
+    let mut statement = db.prepare(
+        "SELECT url, filename, hash, deleted, algorithm, normalisation FROM queue WHERE endpoint=?1 AND next_attempt <= ?2 ORDER BY rowid;",
     )?;
-    if count == 0 {
-        return Ok(String::new());
+    let mut result_iter = statement.query(rusqlite::params![endpoint, now])?;
+
+    while let Some(row) = result_iter.next()? {
+        let filename: Option<String> = row.get(1)?;
+        let hash: Option<String> = row.get(2)?;
+        let algorithm = algorithm_from_db(row.get(4)?);
+        let normalisation: Option<String> = row.get(5)?;
+        let file = filename.map(|file| scan::Filehash {
+            file,
+            hash: hash.unwrap_or_default(),
+            algorithm,
+            normalisation,
+            meta: None,
+        });
+        result.push(payload::PendingUrl {
+            url: row.get(0)?,
+            file,
+            deleted: row.get(3)?,
+        });
     }
-    let result: String = db.query_row(
-        "SELECT sha512 FROM files WHERE filename=:fname;",
-        &[(":fname", f)],
+
+    Ok(result)
+}
+
+pub fn queue_size(db: &rusqlite::Connection) -> Result<u64, Box<dyn Error>> {
+    let count: u64 = db.query_row("SELECT COUNT(DISTINCT url) FROM queue;", [], |row| {
+        row.get(0)
+    })?;
+    Ok(count)
+}
+
+pub fn queue_size_for_endpoint(
+    db: &rusqlite::Connection,
+    endpoint: &str,
+) -> Result<u64, Box<dyn Error>> {
+    let count: u64 = db.query_row(
+        "SELECT COUNT(url) FROM queue WHERE endpoint=?1;",
+        [endpoint],
         |row| row.get(0),
     )?;
-    Ok(result)
+    Ok(count)
 }
 
-pub fn db_update(
+/// Remove queued URLs of endpoints which are no longer configured, so hashes are no longer
+/// held back by them. Returns the removed endpoints.
+pub fn queue_prune_endpoints(
     db: &mut rusqlite::Connection,
-    ins: Vec<scan::Filehash>,
-    upd: Vec<scan::Filehash>,
-    del: Vec<String>,
+    endpoints: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut queued = Vec::<String>::new();
+    {
+        let mut statement = db.prepare("SELECT DISTINCT endpoint FROM queue;")?;
+        let mut result_iter = statement.query([])?;
+        while let Some(row) = result_iter.next()? {
+            queued.push(row.get(0)?);
+        }
+    }
+
+    let mut removed = Vec::<String>::new();
+    for endpoint in queued {
+        if endpoints.contains(&endpoint) {
+            continue;
+        }
+        // queue_done stores the hashes of URLs which are not queued for other endpoints
+        let list = queue_get_due(db, &endpoint, i64::MAX)?;
+        queue_done(db, &endpoint, &list)?;
+        removed.push(endpoint);
+    }
+    Ok(removed)
+}
+
+pub fn queue_done(
+    db: &mut rusqlite::Connection,
+    endpoint: &str,
+    list: &[payload::PendingUrl],
 ) -> Result<(), Box<dyn Error>> {
     let tx = db.transaction()?;
 
-    for i in ins {
+    for p in list {
         tx.execute(
-            "INSERT INTO files (filename, sha512) VALUES (?1, ?2);",
-            [i.file, i.hash],
+            "DELETE FROM queue WHERE url=?1 AND endpoint=?2;",
+            [&p.url, endpoint],
         )?;
+
+        // Store new hash (or remove deleted file) after all endpoints accepted the URL
+        if let Some(f) = &p.file {
+            let remaining: u64 = tx.query_row(
+                "SELECT COUNT(url) FROM queue WHERE url=?1;",
+                [&p.url],
+                |row| row.get(0),
+            )?;
+            if remaining > 0 {
+                continue;
+            }
+            // Metadata is not stored in the queue, the file will be hashed once more on the
+            // next run to store its metadata
+            if p.deleted {
+                tx.execute("DELETE FROM files WHERE filename=?1;", [&f.file])?;
+            } else {
+                tx.execute(
+                    "INSERT OR REPLACE INTO files (filename, hash, algorithm, normalisation) VALUES (?1, ?2, ?3, ?4);",
+                    rusqlite::params![f.file, f.hash, f.algorithm.as_str(), f.normalisation],
+                )?;
+            }
+        }
     }
 
-    for u in upd {
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn queue_failed(
+    db: &mut rusqlite::Connection,
+    endpoint: &str,
+    list: &[payload::PendingUrl],
+    error: &str,
+    now: i64,
+) -> Result<(), Box<dyn Error>> {
+    let tx = db.transaction()?;
+
+    for p in list {
         tx.execute(
-            "UPDATE files SET sha512=?1 WHERE filename=?2;",
-            [u.hash, u.file],
+            "UPDATE queue SET attempts=attempts+1, last_error=?1, next_attempt=?2 + MIN(?3 << MIN(attempts, 16), ?4) WHERE url=?5 AND endpoint=?6;",
+            rusqlite::params![
+                error,
+                now,
+                constants::QUEUE_RETRY_DELAY,
+                constants::QUEUE_MAX_RETRY_DELAY,
+                p.url,
+                endpoint
+            ],
         )?;
     }
 
-    for d in del {
-        tx.execute("DELETE FROM files WHERE filename=?1", [d])?;
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn state_get(db: &rusqlite::Connection, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let mut statement = db.prepare("SELECT value FROM state WHERE key=?1;")?;
+    let mut result_iter = statement.query([key])?;
+
+    match result_iter.next()? {
+        Some(row) => Ok(row.get(0)?),
+        None => Ok(None),
+    }
+}
+
+pub fn state_set(db: &rusqlite::Connection, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+    db.execute(
+        "INSERT OR REPLACE INTO state (key, value) VALUES (?1, ?2);",
+        [key, value],
+    )?;
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub batch: String,
+    pub endpoint: String,
+    pub url: String,
+    pub deleted: bool,
+    pub timestamp: i64,
+    pub status: Option<u16>,
+    pub response: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Record the submission of a batch of URLs to an endpoint
+pub fn history_add(
+    db: &mut rusqlite::Connection,
+    batch_id: &str,
+    endpoint: &str,
+    list: &[payload::PendingUrl],
+    timestamp: i64,
+    reply: &http::Reply,
+    error: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let tx = db.transaction()?;
+
+    for p in list {
+        tx.execute(
+            "INSERT INTO history (batch, endpoint, url, deleted, timestamp, status, response, error) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);",
+            rusqlite::params![
+                batch_id,
+                endpoint,
+                p.url,
+                p.deleted,
+                timestamp,
+                reply.status,
+                reply.body,
+                error
+            ],
+        )?;
     }
 
     tx.commit()?;
     Ok(())
 }
+
+/// Submission history of url (or of all URLs starting with url if prefix is set) between since
+/// and until
+pub fn history_get(
+    db: &rusqlite::Connection,
+    url: &str,
+    prefix: bool,
+    since: Option<i64>,
+    until: Option<i64>,
+) -> Result<Vec<HistoryEntry>, Box<dyn Error>> {
+    let mut result = Vec::<HistoryEntry>::new();
+    let mut statement = db.prepare(
+        "SELECT batch, endpoint, url, deleted, timestamp, status, response, error FROM history WHERE (url = ?1 OR (?2 AND substr(url, 1, length(?1)) = ?1)) AND timestamp >= ?3 AND timestamp < ?4 ORDER BY timestamp, rowid;",
+    )?;
+    let mut result_iter = statement.query(rusqlite::params![
+        url,
+        prefix,
+        since.unwrap_or(i64::MIN),
+        until.unwrap_or(i64::MAX)
+    ])?;
+
+    while let Some(row) = result_iter.next()? {
+        result.push(HistoryEntry {
+            batch: row.get(0)?,
+            endpoint: row.get(1)?,
+            url: row.get(2)?,
+            deleted: row.get(3)?,
+            timestamp: row.get(4)?,
+            status: row.get(5)?,
+            response: row.get(6)?,
+            error: row.get(7)?,
+        });
+    }
+
+    Ok(result)
+}
+
+/// Remove entries of the submission history older than before, returns the number of removed entries
+pub fn history_prune(db: &rusqlite::Connection, before: i64) -> Result<usize, Box<dyn Error>> {
+    let count = db.execute("DELETE FROM history WHERE timestamp < ?1;", [before])?;
+    Ok(count)
+}
+
+/// Time of the last accepted submission to an endpoint
+pub fn history_last_success(
+    db: &rusqlite::Connection,
+    endpoint: &str,
+) -> Result<Option<i64>, Box<dyn Error>> {
+    let last: Option<i64> = db.query_row(
+        "SELECT MAX(timestamp) FROM history WHERE endpoint=?1 AND error IS NULL;",
+        [endpoint],
+        |row| row.get(0),
+    )?;
+    Ok(last)
+}
+
+/// Export content of all tables as JSON
+pub fn export(db: &rusqlite::Connection) -> Result<serde_json::Value, Box<dyn Error>> {
+    let mut result = serde_json::Map::new();
+    result.insert(
+        "schema_version".to_string(),
+        serde_json::Value::from(schema_version(db)?),
+    );
+
+    for table in constants::SQLITE3_EXPORT_TABLES {
+        let mut rows = Vec::<serde_json::Value>::new();
+        let mut statement = db.prepare(&format!("SELECT * FROM {} ORDER BY rowid;", table))?;
+        let columns: Vec<String> = statement
+            .column_names()
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        let mut result_iter = statement.query([])?;
+
+        while let Some(row) = result_iter.next()? {
+            let mut entry = serde_json::Map::new();
+            for (i, column) in columns.iter().enumerate() {
+                let value = match row.get_ref(i)? {
+                    ValueRef::Null => serde_json::Value::Null,
+                    ValueRef::Integer(v) => serde_json::Value::from(v),
+                    ValueRef::Real(v) => serde_json::Value::from(v),
+                    ValueRef::Text(v) => {
+                        serde_json::Value::from(String::from_utf8_lossy(v).to_string())
+                    }
+                    ValueRef::Blob(v) => serde_json::Value::from(hex::encode(v)),
+                };
+                entry.insert(column.clone(), value);
+            }
+            rows.push(serde_json::Value::Object(entry));
+        }
+        result.insert(table.to_string(), serde_json::Value::Array(rows));
+    }
+    Ok(serde_json::Value::Object(result))
+}
+
+/// Replace content of all tables by the content of an export, returns the number of imported rows
+pub fn import(
+    db: &mut rusqlite::Connection,
+    data: &serde_json::Value,
+    dry_run: bool,
+) -> Result<usize, Box<dyn Error>> {
+    let version = match data.get("schema_version").and_then(|v| v.as_u64()) {
+        Some(v) => v as usize,
+        None => bail!("Schema version is missing, data is not an export of the database"),
+    };
+    if version > MIGRATIONS.len() {
+        bail!(
+            "Export has schema version {}, but this version of {} only supports schema versions up to {}",
+            version,
+            constants::NAME,
+            MIGRATIONS.len()
+        );
+    }
+
+    let mut count: usize = 0;
+    let tx = db.transaction()?;
+    for table in constants::SQLITE3_EXPORT_TABLES {
+        let rows = match data.get(table) {
+            Some(serde_json::Value::Array(v)) => v,
+            Some(_) => bail!("Content of table {} is not a list", table),
+            None => continue,
+        };
+
+        // Column names are used in the SQL statement, only accept known columns
+        let mut columns = HashSet::<String>::new();
+        {
+            let mut statement = tx.prepare(&format!("PRAGMA table_info({});", table))?;
+            let mut result_iter = statement.query([])?;
+            while let Some(row) = result_iter.next()? {
+                columns.insert(row.get(1)?);
+            }
+        }
+
+        tx.execute(&format!("DELETE FROM {};", table), [])?;
+        for row in rows {
+            let entry = match row.as_object() {
+                Some(v) => v,
+                None => bail!("Row of table {} is not a mapping of columns", table),
+            };
+            let mut names = Vec::<&str>::new();
+            let mut values = Vec::<rusqlite::types::Value>::new();
+            for (column, value) in entry {
+                if !columns.contains(column) {
+                    bail!("Table {} has no column {}", table, column);
+                }
+                names.push(column);
+                values.push(match value {
+                    serde_json::Value::Null => rusqlite::types::Value::Null,
+                    serde_json::Value::Bool(v) => rusqlite::types::Value::Integer(*v as i64),
+                    serde_json::Value::Number(v) => match v.as_i64() {
+                        Some(i) => rusqlite::types::Value::Integer(i),
+                        None => rusqlite::types::Value::Real(v.as_f64().unwrap_or_default()),
+                    },
+                    serde_json::Value::String(v) => rusqlite::types::Value::Text(v.clone()),
+                    _ => bail!("Invalid value for column {} of table {}", column, table),
+                });
+            }
+
+            let placeholders: Vec<String> = (1..=names.len()).map(|i| format!("?{}", i)).collect();
+            tx.execute(
+                &format!(
+                    "INSERT INTO {} ({}) VALUES ({});",
+                    table,
+                    names.join(", "),
+                    placeholders.join(", ")
+                ),
+                rusqlite::params_from_iter(values),
+            )?;
+            count += 1;
+        }
+    }
+
+    if dry_run {
+        tx.rollback()?;
+    } else {
+        tx.commit()?;
+    }
+    Ok(count)
+}
+
+pub fn vacuum(db: &rusqlite::Connection) -> Result<(), Box<dyn Error>> {
+    db.execute("VACUUM;", [])?;
+    Ok(())
+}
+
+/// Remove files (or all files below a directory) and URLs from the database and the queue, returns
+/// the number of removed files and queued URLs
+pub fn forget(
+    db: &mut rusqlite::Connection,
+    entries: &[String],
+    dry_run: bool,
+) -> Result<(usize, usize), Box<dyn Error>> {
+    let mut files: usize = 0;
+    let mut queued: usize = 0;
+    let tx = db.transaction()?;
+
+    for entry in entries {
+        let dir = format!("{}/", entry.trim_end_matches('/'));
+        files += tx.execute(
+            "DELETE FROM files WHERE filename=?1 OR substr(filename, 1, length(?2))=?2;",
+            [entry, &dir],
+        )?;
+        queued += tx.execute(
+            "DELETE FROM queue WHERE url=?1 OR filename=?1 OR substr(filename, 1, length(?2))=?2;",
+            [entry, &dir],
+        )?;
+    }
+
+    if dry_run {
+        tx.rollback()?;
+    } else {
+        tx.commit()?;
+    }
+    Ok((files, queued))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(db: &rusqlite::Connection, table: &str) -> Vec<String> {
+        let mut statement = db
+            .prepare(&format!("PRAGMA table_info({});", table))
+            .unwrap();
+        let result = statement
+            .query_map([], |row| row.get(1))
+            .unwrap()
+            .collect::<Result<Vec<String>>>()
+            .unwrap();
+        result
+    }
+
+    #[test]
+    fn migrate_baseline_schema() {
+        // Schema of the first release, without schema version
+        let mut db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE files (filename TEXT PRIMARY KEY, sha512 VARCHAR(128));
+            INSERT INTO files (filename, sha512) VALUES ('/srv/www/index.html', 'abc');",
+        )
+        .unwrap();
+        assert_eq!(schema_version(&db).unwrap(), 0);
+
+        migrate(&mut db, ":memory:", false).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), latest_schema_version());
+
+        let files = columns(&db, "files");
+        assert!(files.contains(&"hash".to_string()));
+        assert!(!files.contains(&"sha512".to_string()));
+        for (name, _) in constants::SQLITE3_FILES_COLUMNS {
+            assert!(files.contains(&name.to_string()), "{}", name);
+        }
+        for table in ["queue", "state", "history"] {
+            assert!(!columns(&db, table).is_empty(), "{}", table);
+        }
+
+        let (hash, algorithm): (String, Option<String>) = db
+            .query_row(
+                "SELECT hash, algorithm FROM files WHERE filename = '/srv/www/index.html';",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(hash, "abc");
+        assert_eq!(algorithm, None);
+        assert_eq!(file_count(&db).unwrap(), 1);
+
+        // Migrating an up to date database doesn't change anything
+        migrate(&mut db, ":memory:", false).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), latest_schema_version());
+    }
+
+    #[test]
+    fn migrate_dry_run_keeps_schema() {
+        let mut db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch("CREATE TABLE files (filename TEXT PRIMARY KEY, sha512 VARCHAR(128));")
+            .unwrap();
+
+        migrate(&mut db, ":memory:", true).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), 0);
+        assert_eq!(columns(&db, "files"), vec!["filename", "sha512"]);
+    }
+
+    #[test]
+    fn migrate_rejects_newer_schema() {
+        let mut db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(&format!(
+            "PRAGMA user_version = {};",
+            latest_schema_version() + 1
+        ))
+        .unwrap();
+        assert!(migrate(&mut db, ":memory:", false).is_err());
+        assert_eq!(schema_version(&db).unwrap(), latest_schema_version() + 1);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::config;
+use crate::git;
+use crate::history;
+use crate::payload;
+use crate::scan;
+use crate::sqlite3;
+
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SiteStatus {
+    pub site: String,
+    pub database: String,
+    pub database_size: u64,
+    pub schema_version: usize,
+    pub latest_schema_version: usize,
+    pub integrity: Vec<String>,
+    pub files: u64,
+    pub queued: u64,
+    // Not available for sitemaps, because all pages would have to be fetched
+    pub changed: Option<usize>,
+    pub deleted: Option<usize>,
+    pub endpoints: Vec<EndpointStatus>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct EndpointStatus {
+    pub endpoint: String,
+    pub queued: u64,
+    pub last_success: Option<i64>,
+}
+
+impl SiteStatus {
+    pub fn is_healthy(&self) -> bool {
+        self.integrity.len() == 1
+            && self.integrity[0] == "ok"
+            && self.schema_version <= self.latest_schema_version
+    }
+}
+
+/// Collect status of the database of a site and count the files changed since the last run,
+/// the database is opened read-only and pending migrations are not applied
+pub fn collect(
+    cfg: &config::Configuration,
+    roots: &[config::HtmlRoot],
+) -> Result<SiteStatus, Box<dyn Error>> {
+    let mut db = sqlite3::open_read_only(&cfg.database)?;
+    let schema_version = sqlite3::schema_version(&db)?;
+    let latest_schema_version = sqlite3::latest_schema_version();
+
+    let mut status = SiteStatus {
+        site: cfg.name.clone(),
+        database: cfg.database.clone(),
+        database_size: fs::metadata(&cfg.database)?.len(),
+        schema_version,
+        latest_schema_version,
+        integrity: sqlite3::integrity_check(&db)?,
+        files: 0,
+        queued: 0,
+        changed: None,
+        deleted: None,
+        endpoints: Vec::new(),
+    };
+
+    // Tables and columns of other schema versions differ, the table of files exists since the first one
+    if schema_version <= latest_schema_version {
+        status.files = sqlite3::file_count(&db)?;
+    }
+    if schema_version != latest_schema_version {
+        return Ok(status);
+    }
+
+    for endpoint in &cfg.submit_list {
+        status.endpoints.push(EndpointStatus {
+            endpoint: endpoint.clone(),
+            queued: sqlite3::queue_size_for_endpoint(&db, endpoint)?,
+            last_success: sqlite3::history_last_success(&db, endpoint)?,
+        });
+    }
+
+    if let Some((c, d)) = count_changes(cfg, &mut db, roots)? {
+        status.changed = Some(c);
+        status.deleted = Some(d);
+    }
+    status.queued = sqlite3::queue_size(&db)?;
+    Ok(status)
+}
+
+// Changed and deleted files, the database is not modified
+fn count_changes(
+    cfg: &config::Configuration,
+    db: &mut rusqlite::Connection,
+    roots: &[config::HtmlRoot],
+) -> Result<Option<(usize, usize)>, Box<dyn Error>> {
+    if cfg.sitemap.is_some() {
+        return Ok(None);
+    }
+    let extlist = match &cfg.file_extensions {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    if let Some(git_cfg) = &cfg.git {
+        let changes = git::build_update_list(db, git_cfg, extlist, None)?;
+        let count = |list: &[String]| {
+            list.iter()
+                .filter(|f| !payload::is_excluded(&cfg.exclude_list, f))
+                .count()
+        };
+        return Ok(Some((count(&changes.changed), count(&changes.deleted))));
+    }
+
+    // Without roots and without path to the HTML data there is nothing to compare with
+    if roots.is_empty() {
+        return Ok(None);
+    }
+
+    let mut changed: usize = 0;
+    let mut deleted: usize = 0;
+    for root in roots {
+        let changes = scan::build_update_list(&root.path, db, cfg, extlist, true, false, None)?;
+        let (trimmed, _) = payload::remove_excludes(&cfg.exclude_list, changes);
+        changed += trimmed.changed.len();
+        deleted += trimmed.deleted.len();
+    }
+    Ok(Some((changed, deleted)))
+}
+
+pub fn print(list: &[SiteStatus], json: bool) -> Result<(), Box<dyn Error>> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(&list)?);
+        return Ok(());
+    }
+
+    for (i, s) in list.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("Site:             {}", s.site);
+        println!(
+            "Database:         {} ({} bytes, schema version {})",
+            s.database, s.database_size, s.schema_version
+        );
+        if s.schema_version < s.latest_schema_version {
+            println!(
+                "Schema:           outdated, run db migrate to update to schema version {}",
+                s.latest_schema_version
+            );
+        } else if s.schema_version > s.latest_schema_version {
+            println!(
+                "Schema:           not supported, this version supports schema versions up to {}",
+                s.latest_schema_version
+            );
+        }
+        println!("Integrity:        {}", s.integrity.join("; "));
+        println!("Tracked files:    {}", s.files);
+        match (s.changed, s.deleted) {
+            (Some(c), Some(d)) => println!("Pending changes:  {} changed, {} deleted", c, d),
+            _ => println!("Pending changes:  unknown"),
+        };
+        println!("Queued URLs:      {}", s.queued);
+        for e in &s.endpoints {
+            let last = match e.last_success {
+                Some(v) => history::format_time(v),
+                None => "never".to_string(),
+            };
+            println!("Endpoint:         {}", e.endpoint);
+            println!("  Queued URLs:    {}", e.queued);
+            println!("  Last success:   {}", last);
+        }
+    }
+    Ok(())
+}
@@ -1,30 +1,38 @@
+use crate::cli;
 use crate::constants;
 
+/// Show list of commands
 pub fn show_usage() {
     show_version();
-    println!("Usage: {} [-D|--debug] -c <config>|--config=<config> [-h|--help] [-n|--dry-run] [-p|--purge] [-v|--version] [-q|--quiet] /path/to/html
-
-    -D          Enable debug output
-    --debug
-
-    -c <config>         Path to configuration file
-    --config=<config>   This option is mandatory
-
-    -h                  This text
-    --help
-
-    -n                  Process data but print what whould be submitted instead
-    --dry-run           submitting it
+    println!(
+        "Usage: {} [<command>] -c <config>|--config=<config> [<options>] [<arguments>]
 
-    -p                  Purge files found in the database but no
-    --purge             longer present in the filesystem
+Commands:",
+        constants::NAME
+    );
+    for command in &cli::COMMANDS {
+        println!("    {:<10} {}", command.name, command.arguments);
+    }
+    println!(
+        "
+Without command, run is used. Use {} help <command> to show the options of a command.",
+        constants::NAME
+    );
+}
 
-    -v                  Show version information
-    --version
+/// Show help text of a command
+pub fn show_command_usage(command: &cli::Command) {
+    show_version();
+    let brief = format!(
+        "Usage: {} {} -c <config>|--config=<config> [<options>] {}
 
-    -q                  Quiet operation
-    --quiet             Only errors will be logged
-", constants::NAME)
+{}",
+        constants::NAME,
+        command.name,
+        command.arguments,
+        command.description
+    );
+    print!("{}", cli::options(command.name).usage(&brief));
 }
 
 pub fn show_version() {
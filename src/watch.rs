@@ -0,0 +1,247 @@
+use crate::config;
+use crate::constants;
+use crate::payload;
+use crate::sqlite3;
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use log::{debug, error, info, warn};
+use simple_error::bail;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+enum WatchEvent {
+    Touched(String),
+    // Events were lost, all files must be checked
+    Overflow,
+    Failed(String),
+}
+
+/// Watch HTML data for changes and submit changed files. All files are checked once after the
+/// watches are set up, so changes while the program was not running are submitted too.
+pub fn watch(
+    cfg: &config::Configuration,
+    db: &mut rusqlite::Connection,
+    roots: &[config::HtmlRoot],
+    purge: bool,
+    rehash: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut inotify = Inotify::init()?;
+    let mut dirs = HashMap::<WatchDescriptor, String>::new();
+    for root in roots {
+        add_watches(&mut inotify, root.path.trim_end_matches('/'), &mut dirs)?;
+    }
+    info!("Watching {} directories", dirs.len());
+
+    let (tx, rx) = mpsc::channel::<WatchEvent>();
+    thread::spawn(move || read_events(inotify, dirs, tx));
+
+    if let Err(e) = payload::submit_roots(cfg, db, roots, None, purge, rehash, dry_run) {
+        error!("{}", e);
+    }
+
+    let debounce = Duration::from_secs(cfg.watch.debounce);
+    let max_delay = Duration::from_secs(cfg.watch.max_delay);
+    let queue_interval = Duration::from_secs(constants::QUEUE_RETRY_DELAY as u64);
+
+    loop {
+        let mut touched = BTreeSet::<String>::new();
+        let mut overflow = false;
+
+        // Failed submissions are retried even if nothing changes
+        match rx.recv_timeout(queue_interval) {
+            Ok(ev) => handle_event(ev, &mut touched, &mut overflow)?,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if sqlite3::queue_size(db)? > 0 {
+                    debug!("Processing queue of pending submissions");
+                    if let Err(e) = payload::submit_pending(cfg, db, roots, Vec::new(), dry_run) {
+                        error!("{}", e);
+                    }
+                }
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => bail!("watcher thread has stopped"),
+        };
+
+        // Wait until no new events arrive for the debounce time, e.g. during a site rebuild,
+        // but not longer than the maximal delay
+        let started = Instant::now();
+        loop {
+            let remaining = max_delay.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                debug!("Maximal delay for watch mode reached, processing changes");
+                break;
+            }
+            match rx.recv_timeout(debounce.min(remaining)) {
+                Ok(ev) => handle_event(ev, &mut touched, &mut overflow)?,
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => bail!("watcher thread has stopped"),
+            };
+        }
+
+        let result = if overflow {
+            warn!("Events of changed files were lost, checking all files");
+            payload::submit_roots(cfg, db, roots, None, false, false, dry_run)
+        } else {
+            info!("Checking {} changed paths", touched.len());
+            let files: Vec<String> = touched.into_iter().collect();
+            payload::submit_roots(cfg, db, roots, Some(&files), false, false, dry_run)
+        };
+        if let Err(e) = result {
+            error!("{}", e);
+        }
+    }
+}
+
+fn handle_event(
+    ev: WatchEvent,
+    touched: &mut BTreeSet<String>,
+    overflow: &mut bool,
+) -> Result<(), Box<dyn Error>> {
+    match ev {
+        WatchEvent::Touched(p) => {
+            debug!("{} has been changed", p);
+            touched.insert(p);
+        }
+        WatchEvent::Overflow => *overflow = true,
+        WatchEvent::Failed(e) => bail!("can't read inotify events: {}", e),
+    };
+    Ok(())
+}
+
+/// Add watches for directory dir and all directories below it, returns the files found
+fn add_watches(
+    inotify: &mut Inotify,
+    dir: &str,
+    dirs: &mut HashMap<WatchDescriptor, String>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mask = WatchMask::CLOSE_WRITE
+        | WatchMask::CREATE
+        | WatchMask::DELETE
+        | WatchMask::MOVED_FROM
+        | WatchMask::MOVED_TO
+        | WatchMask::ONLYDIR
+        | WatchMask::EXCL_UNLINK;
+    let mut files = Vec::<String>::new();
+
+    for entry in WalkDir::new(dir) {
+        let fs_obj = match entry {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Skipping: {}", e);
+                continue;
+            }
+        };
+        let path = match fs_obj.path().to_str() {
+            Some(v) => v.trim_end_matches('/').to_string(),
+            None => {
+                warn!("Invalid filename {} - skipping", fs_obj.path().display());
+                continue;
+            }
+        };
+
+        if !fs_obj.file_type().is_dir() {
+            files.push(path);
+            continue;
+        }
+
+        debug!("Adding watch for {}", path);
+        let wd = inotify.watches().add(&path, mask)?;
+        dirs.insert(wd, path);
+    }
+    Ok(files)
+}
+
+/// Remove watches for directory dir and all directories below it
+fn remove_watches(inotify: &mut Inotify, dir: &str, dirs: &mut HashMap<WatchDescriptor, String>) {
+    let prefix = format!("{}/", dir);
+    let removed: Vec<WatchDescriptor> = dirs
+        .iter()
+        .filter(|(_, d)| *d == dir || d.starts_with(&prefix))
+        .map(|(wd, _)| wd.clone())
+        .collect();
+
+    for wd in removed {
+        debug!("Removing watch for {:?}", dirs.get(&wd));
+        if let Err(e) = inotify.watches().remove(wd.clone()) {
+            debug!("Can't remove watch: {}", e);
+        }
+        dirs.remove(&wd);
+    }
+}
+
+fn read_events(
+    mut inotify: Inotify,
+    mut dirs: HashMap<WatchDescriptor, String>,
+    tx: mpsc::Sender<WatchEvent>,
+) {
+    let mut buffer = vec![0; constants::WATCH_EVENT_BUFFER_SIZE];
+
+    loop {
+        let events = match inotify.read_events_blocking(&mut buffer) {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = tx.send(WatchEvent::Failed(e.to_string()));
+                return;
+            }
+        };
+
+        for event in events {
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                if tx.send(WatchEvent::Overflow).is_err() {
+                    return;
+                }
+                continue;
+            }
+            // Watch was removed, e.g. because the directory was removed
+            if event.mask.contains(EventMask::IGNORED) {
+                dirs.remove(&event.wd);
+                continue;
+            }
+
+            let dir = match dirs.get(&event.wd) {
+                Some(v) => v,
+                None => continue,
+            };
+            let name = match event.name.and_then(|n| n.to_str()) {
+                Some(v) => v,
+                None => continue,
+            };
+            let path = format!("{}/{}", dir, name);
+
+            let mut touched = Vec::<String>::new();
+            if event.mask.contains(EventMask::ISDIR) {
+                if event
+                    .mask
+                    .intersects(EventMask::CREATE | EventMask::MOVED_TO)
+                {
+                    // Files can be created before the watch for a new directory is added
+                    match add_watches(&mut inotify, &path, &mut dirs) {
+                        Ok(v) => touched = v,
+                        Err(e) => warn!("Can't watch directory {}: {}", path, e),
+                    };
+                } else {
+                    // A directory moved away is still watched at its new location
+                    if event.mask.contains(EventMask::MOVED_FROM) {
+                        remove_watches(&mut inotify, &path, &mut dirs);
+                    }
+                    // Files below removed directories are removed too
+                    touched.push(path);
+                }
+            } else if !event.mask.contains(EventMask::CREATE) {
+                // Content of new files is reported by CLOSE_WRITE
+                touched.push(path);
+            }
+
+            for t in touched {
+                if tx.send(WatchEvent::Touched(t)).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}